@@ -1,7 +1,12 @@
 #include "llvm-c/Target.h"
 #include "llvm/IR/DataLayout.h"
+#include "llvm/IR/Instructions.h"
 #include "llvm/IR/Module.h"
 
 extern "C" unsigned int LLVMCompilispGetTypeAllocSize(LLVMTargetDataRef D, LLVMTypeRef Ty) {
     return llvm::unwrap(D)->getTypeAllocSize(llvm::unwrap(Ty));
-}
\ No newline at end of file
+}
+
+extern "C" void LLVMCompilispSetMustTailCall(LLVMValueRef Call) {
+    llvm::unwrap<llvm::CallInst>(Call)->setTailCallKind(llvm::CallInst::TCK_MustTail);
+}
@@ -1,17 +1,173 @@
 #[derive(Clone, Debug)]
 pub enum Expr {
     Number(i32),
+    /// Inexact number
+    Real(f64),
+    /// Integer too large for a fixnum, as its decimal digits
+    Bignum(String),
     Boolean(bool),
+    Char(char),
     Symbol(String),
     String(String),
     List(Vec<Expr>),
+    /// Constant datum, lists and symbols in it are data instead of calls and variables
+    Quote(Box<Expr>),
+    /// Backquote template, its unquoted parts are `(unquote expr)` and `(unquote-splicing expr)`
+    /// lists in the datum
+    Quasiquote(Box<Expr>),
+    /// Vector literal, its items are quoted data
+    Vector(Vec<Expr>),
     Procedure(String, Vec<Expr>),
     LetProcedure(Vec<(String, Expr)>, Box<Expr>),
+    NamedLetProcedure(String, Vec<(String, Expr)>, Box<Expr>),
+    LetStarProcedure(Vec<(String, Expr)>, Box<Expr>),
+    LetrecProcedure(Vec<(String, Expr)>, Box<Expr>),
+    LetrecStarProcedure(Vec<(String, Expr)>, Box<Expr>),
+    Lambda(Formals, Box<Expr>),
     DefineExpr(String, Box<Expr>),
-    DefineProcedure(String, Vec<String>, Box<Expr>),
+    DefineProcedure(String, Formals, Box<Expr>),
+    Set(String, Box<Expr>),
+    /// Variables with init and optional step, termination test, result and body expressions
+    Do(
+        Vec<(String, Expr, Option<Expr>)>,
+        Box<Expr>,
+        Vec<Expr>,
+        Vec<Expr>,
+    ),
+    /// Clauses and optional `else` body
+    Cond(Vec<CondClause>, Option<Vec<Expr>>),
+    /// Key, clauses with their data and body, and optional `else` body
+    Case(Box<Expr>, Vec<(Vec<Expr>, Vec<Expr>)>, Option<Vec<Expr>>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    When(Box<Expr>, Vec<Expr>),
+    Unless(Box<Expr>, Vec<Expr>),
+    DefineSyntax(String, SyntaxRules),
+    LetSyntax(Vec<(String, SyntaxRules)>, Box<Expr>),
+    /// Macros of `letrec-syntax` can use each other and themselves
+    LetrecSyntax(Vec<(String, SyntaxRules)>, Box<Expr>),
     Error,
 }
 
+/// Expressions evaluated in order, the value is the one of the last expression
+pub fn sequence(mut body: Vec<Expr>) -> Expr {
+    if body.len() == 1 {
+        body.remove(0)
+    } else {
+        Expr::Procedure("begin".to_owned(), body)
+    }
+}
+
+/// Character of a `#\\` literal, written as itself, by its name or as `x` and its hex code
+pub fn char_literal(literal: &str) -> Option<char> {
+    let mut chars = literal.chars();
+    match (chars.next(), chars.next()) {
+        (Some(value), None) => Some(value),
+        _ => match literal {
+            "space" => Some(' '),
+            "newline" | "linefeed" => Some('\n'),
+            "tab" => Some('\t'),
+            "return" => Some('\r'),
+            "null" | "nul" => Some('\0'),
+            "alarm" => Some('\x07'),
+            "backspace" => Some('\x08'),
+            "escape" | "altmode" => Some('\x1b'),
+            "delete" | "rubout" => Some('\x7f'),
+            _ => literal
+                .strip_prefix('x')
+                .and_then(|code| u32::from_str_radix(code, 16).ok())
+                .and_then(char::from_u32),
+        },
+    }
+}
+
+/// Contents of a string literal with its escapes replaced, a backslash at the end of a line
+/// continues the string on the next line without the surrounding whitespace
+pub fn string_literal(literal: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut chars = literal.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'a' => value.push('\x07'),
+            'b' => value.push('\x08'),
+            't' => value.push('\t'),
+            'n' => value.push('\n'),
+            'r' => value.push('\r'),
+            c @ ('"' | '\\' | '|') => value.push(c),
+            'x' => {
+                // Hex escapes must end with a semicolon
+                let mut code = String::new();
+                loop {
+                    match chars.next()? {
+                        ';' => break,
+                        c => code.push(c),
+                    }
+                }
+                value.push(
+                    u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)?,
+                );
+            }
+            c if c.is_whitespace() => {
+                // Only whitespace can follow the backslash up to the line ending
+                let mut line_ending = c == '\n';
+                while let Some(&c) = chars.peek() {
+                    if !c.is_whitespace() || (line_ending && c == '\n') {
+                        break;
+                    }
+                    line_ending |= c == '\n';
+                    chars.next();
+                }
+                if !line_ending {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+/// Procedure parameters, arguments after the fixed ones are passed to `rest` as a list
+#[derive(Clone, Debug, Default)]
+pub struct Formals {
+    pub args: Vec<String>,
+    pub rest: Option<String>,
+}
+
+impl Formals {
+    pub fn new(args: Vec<String>) -> Self {
+        Self { args, rest: None }
+    }
+
+    /// Symbols bound by the parameters, the rest parameter is the last one
+    pub fn symbols(&self) -> impl Iterator<Item = &String> {
+        self.args.iter().chain(&self.rest)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum CondClause {
+    /// Test and body expressions. Without body, the test value is the clause result
+    Test(Expr, Vec<Expr>),
+    /// Test whose value is passed to the receiver procedure, `(test => receiver)`
+    Receiver(Expr, Expr),
+}
+
+/// Macro transformer, each rule has a pattern and the template it expands to.
+/// Patterns and templates are data, like quoted lists
+#[derive(Clone, Debug)]
+pub struct SyntaxRules {
+    pub ellipsis: String,
+    pub literals: Vec<String>,
+    pub rules: Vec<(Expr, Expr)>,
+}
+
 #[derive(Debug)]
 pub struct ModuleAst {
     pub source: String,
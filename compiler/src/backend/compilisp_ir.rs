@@ -1,13 +1,129 @@
-use crate::ast::Expr;
+use crate::ast::{Expr, Formals};
+use crate::backend::error::CompilispError;
 use std::collections::HashMap;
 
 pub type AllocId = usize;
 
+/// Procedures implemented by the runtime, called when their name isn't bound by the module
+const RUNTIME_PROCEDURES: &[&str] = &[
+    "+",
+    "-",
+    "display",
+    "<",
+    ">",
+    "eq?",
+    "eqv?",
+    "cons",
+    "car",
+    "cdr",
+    "list",
+    "null?",
+    "pair?",
+    "vector",
+    "make-vector",
+    "vector-ref",
+    "vector-set!",
+    "vector-length",
+    "vector->list",
+    "list->vector",
+    "append",
+    "write",
+    "char->integer",
+    "integer->char",
+    "char=?",
+    "char<?",
+    "char>?",
+    "char<=?",
+    "char>=?",
+    "char-upcase",
+    "char-downcase",
+    "char-alphabetic?",
+    "char-numeric?",
+    "char-whitespace?",
+    "exact->inexact",
+    "inexact->exact",
+    "exact",
+    "inexact",
+    "floor",
+    "ceiling",
+    "truncate",
+    "round",
+    "*",
+    "/",
+    "=",
+    "<=",
+    ">=",
+    "quotient",
+    "remainder",
+    "modulo",
+    "abs",
+    "min",
+    "max",
+    "zero?",
+    "positive?",
+    "negative?",
+    "even?",
+    "odd?",
+    "string-length",
+    "string-ref",
+    "substring",
+    "string-append",
+    "string=?",
+    "string<?",
+    "string>?",
+    "string<=?",
+    "string>=?",
+    "string-upcase",
+    "string-downcase",
+    "string->number",
+    "number->string",
+    "string->symbol",
+    "symbol->string",
+];
+
+/// Runtime procedures always returning a boolean, conditionals on them skip the type check
+const BOOLEAN_PRIMITIVES: &[&str] = &[
+    "=",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "zero?",
+    "positive?",
+    "negative?",
+    "even?",
+    "odd?",
+    "eq?",
+    "eqv?",
+    "null?",
+    "pair?",
+    "char=?",
+    "char<?",
+    "char>?",
+    "char<=?",
+    "char>=?",
+    "char-alphabetic?",
+    "char-numeric?",
+    "char-whitespace?",
+    "string=?",
+    "string<?",
+    "string>?",
+    "string<=?",
+    "string>=?",
+];
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum AllocType {
     Int,
+    Real,
     String,
     Bool,
+    Char,
+    Symbol,
+    Procedure,
+    Unspecified,
+    /// Value whose type is only known at runtime, like call results and variables
+    Object,
 }
 
 #[derive(Clone, Debug)]
@@ -22,55 +138,175 @@ pub enum CompilispIr {
         name: String,
         return_id: AllocId,
         args: Vec<Alloc>,
+        env: Vec<Alloc>,
+        tail: bool,
+    },
+    CallRuntime {
+        name: String,
+        return_id: AllocId,
+        args: Vec<Alloc>,
+    },
+    CallClosure {
+        closure_alloc: AllocId,
+        return_id: AllocId,
+        args: Vec<Alloc>,
+        tail: bool,
+    },
+    MakeClosure {
+        alloc_id: AllocId,
+        function_name: String,
+        env: Vec<Alloc>,
     },
     ConstInt {
         alloc_id: AllocId,
         value: i32,
     },
+    ConstReal {
+        alloc_id: AllocId,
+        value: f64,
+    },
+    /// Bignum parsed by the runtime from its decimal digits
+    GlobalBignum {
+        alloc_id: AllocId,
+        value: String,
+    },
+    ConstBool {
+        alloc_id: AllocId,
+        value: bool,
+    },
+    ConstChar {
+        alloc_id: AllocId,
+        value: char,
+    },
+    ConstUnspecified {
+        alloc_id: AllocId,
+    },
     GlobalString {
         alloc_id: AllocId,
         value: String,
     },
+    /// Symbol interned by the runtime, equal symbols are the same object
+    GlobalSymbol {
+        alloc_id: AllocId,
+        value: String,
+    },
+    /// Every value but `#f` is true, known booleans are tested without checking their type
     IfExpressionEval {
         cond_alloc: AllocId,
+        known_boolean: bool,
     },
     IfExpressionElse,
+    /// Copies the branch result into the if expression result
     IfExpressionEndElse {
         result_alloc: AllocId,
-        cond_alloc: AllocId,
+        if_alloc: AllocId,
     },
     IfExpressionEndThen {
         result_alloc: AllocId,
-        cond_alloc: AllocId,
+        if_alloc: AllocId,
     },
     IfExpressionEndBlock,
-    IfExpressionEndExpression {
-        result_alloc: AllocId,
+    LoopHeader,
+    LoopBody {
+        cond_alloc: AllocId,
+        known_boolean: bool,
+    },
+    LoopBackEdge,
+    DeclareVariable {
+        alloc_id: AllocId,
+    },
+    DefineVariable {
+        alloc_id: AllocId,
+        value_alloc: AllocId,
+    },
+    SetVariable {
+        alloc_id: AllocId,
+        value_alloc: AllocId,
+    },
+    /// Copies a value into a new box, shared by the closures capturing it
+    MakeBox {
+        alloc_id: AllocId,
+        value_alloc: AllocId,
+    },
+    Unbox {
+        alloc_id: AllocId,
+        box_alloc: AllocId,
+    },
+    SetBox {
+        box_alloc: AllocId,
+        value_alloc: AllocId,
+    },
+    /// Copies the value of a top level variable, stored in a module global
+    LoadGlobal {
+        alloc_id: AllocId,
+        name: String,
+    },
+    SetGlobal {
+        name: String,
+        value_alloc: AllocId,
     },
     ProcedureScopeStart,
     ProcedureScopeEnd,
     ProcedureReturnValue(AllocId),
     StartProcedure(String),
-    MapProcedureArgs(Vec<String>, AllocId),
+    /// Checks the arguments count and maps them, extra arguments are collected in a list
+    MapProcedureArgs(Formals, AllocId),
+    MapProcedureEnv(Vec<String>, AllocId),
+    /// Calls a runtime procedure with the arguments received by the current procedure
+    ForwardRuntimeCall {
+        name: String,
+        return_id: AllocId,
+    },
     EndProcedure(AllocId),
 }
 
+/// A procedure known at compile time, lifted to a module function.
+/// Local symbols it references are captured, and passed in its environment
+#[derive(Clone, Debug)]
+struct LocalProcedure {
+    function_name: String,
+    captures: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Binding {
+    Value(Alloc),
+    /// Assigned variables are boxed, so assignments are seen by the closures capturing them
+    Boxed(Alloc),
+    /// Top level variables are module globals, visible from every procedure without capturing
+    Global(String),
+    Procedure(LocalProcedure),
+}
+
 // Todo: generate ir in a lazy way and make buffer private
 #[derive(Debug)]
 pub struct CompilispIrGenerator {
     pub ir_buffer: Vec<CompilispIr>,
-    symbol_scopes: Vec<HashMap<String, Alloc>>,
+    /// Procedures are emitted here, so they can be built before the code calling them
+    pub procedures_buffer: Vec<CompilispIr>,
+    /// Errors found while processing, the module can't be built if there are any
+    pub errors: Vec<CompilispError>,
+    symbol_scopes: Vec<HashMap<String, Binding>>,
+    /// Runtime procedures already wrapped in a module function, to be used as values
+    wrapped_primitives: Vec<String>,
     alloc_id: usize,
+    /// Whether the next expression to process is the last one evaluated by a procedure
+    tail_position: bool,
 }
 
 impl CompilispIrGenerator {
     pub fn new() -> Self {
         let ir_buffer = vec![];
+        let procedures_buffer = vec![];
         let symbol_scopes = vec![HashMap::new()];
         Self {
             ir_buffer,
+            procedures_buffer,
+            errors: vec![],
             alloc_id: 0,
             symbol_scopes,
+            wrapped_primitives: vec![],
+            tail_position: false,
         }
     }
 
@@ -78,7 +314,41 @@ impl CompilispIrGenerator {
         self.process_expr(root);
     }
 
+    /// Binds the top level definitions before processing the module, so procedures may
+    /// reference variables and procedures defined after them
+    pub fn declare_toplevel(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            match expr {
+                Expr::DefineExpr(name, _) => {
+                    self.push_let_binding(name, Binding::Global(name.clone()));
+                }
+                // Assigned procedures are stored as closures, calls go through their global
+                Expr::DefineProcedure(name, _, _)
+                    if exprs.iter().any(|expr| is_assigned(name, expr)) =>
+                {
+                    self.push_let_binding(name, Binding::Global(name.clone()));
+                }
+                Expr::DefineProcedure(name, _, _) => {
+                    let procedure = LocalProcedure {
+                        function_name: name.clone(),
+                        captures: vec![],
+                    };
+                    self.push_let_binding(name, Binding::Procedure(procedure));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Processes an expression that returns from the current procedure if `tail` is set
+    fn process_tail_expr(&mut self, expr: &Expr, tail: bool) -> Alloc {
+        self.tail_position = tail;
+        self.process_expr(expr)
+    }
+
     fn process_expr(&mut self, expr: &Expr) -> Alloc {
+        // Subexpressions are never in tail position, unless processed with `process_tail_expr`
+        let tail = std::mem::take(&mut self.tail_position);
         match expr {
             Expr::Number(value) => {
                 self.alloc_id += 1;
@@ -91,7 +361,50 @@ impl CompilispIrGenerator {
                     alloc_type: AllocType::Int,
                 }
             }
-
+            Expr::Real(value) => {
+                self.alloc_id += 1;
+                self.ir_buffer.push(CompilispIr::ConstReal {
+                    alloc_id: self.alloc_id,
+                    value: *value,
+                });
+                Alloc {
+                    id: self.alloc_id,
+                    alloc_type: AllocType::Real,
+                }
+            }
+            Expr::Bignum(value) => {
+                self.alloc_id += 1;
+                self.ir_buffer.push(CompilispIr::GlobalBignum {
+                    alloc_id: self.alloc_id,
+                    value: value.clone(),
+                });
+                Alloc {
+                    id: self.alloc_id,
+                    alloc_type: AllocType::Object,
+                }
+            }
+            Expr::Boolean(value) => {
+                self.alloc_id += 1;
+                self.ir_buffer.push(CompilispIr::ConstBool {
+                    alloc_id: self.alloc_id,
+                    value: *value,
+                });
+                Alloc {
+                    id: self.alloc_id,
+                    alloc_type: AllocType::Bool,
+                }
+            }
+            Expr::Char(value) => {
+                self.alloc_id += 1;
+                self.ir_buffer.push(CompilispIr::ConstChar {
+                    alloc_id: self.alloc_id,
+                    value: *value,
+                });
+                Alloc {
+                    id: self.alloc_id,
+                    alloc_type: AllocType::Char,
+                }
+            }
             Expr::String(value) => {
                 self.alloc_id += 1;
                 self.ir_buffer.push(CompilispIr::GlobalString {
@@ -103,49 +416,128 @@ impl CompilispIrGenerator {
                     alloc_type: AllocType::String,
                 }
             }
+            Expr::Quote(datum) => self.build_quoted(datum),
+            // Vector literals are self evaluating
+            Expr::Vector(_) => self.build_quoted(expr),
             Expr::Procedure(name, args) => match name.as_str() {
-                "if" => self.build_if_call(args.as_slice()),
-                _ => self.build_generic_call(name, args),
+                "if" => self.build_if_call(args.as_slice(), tail),
+                "begin" => self.build_sequence(args, tail),
+                _ => self.build_generic_call(name, args, tail),
             },
             Expr::LetProcedure(symbols, expr) => {
+                // Init expressions are evaluated in the outer scope
+                let bindings = symbols
+                    .iter()
+                    .map(|(symbol_name, sym_expr)| {
+                        let assigned = is_assigned(symbol_name, expr);
+                        self.process_binding(symbol_name, sym_expr, assigned)
+                    })
+                    .collect::<Vec<_>>();
                 self.push_let_context();
-                for (symbol_name, sym_expr) in symbols {
-                    let alloc = self.process_expr(sym_expr);
-                    self.push_let_binding(symbol_name, alloc);
+                for ((symbol_name, _), binding) in symbols.iter().zip(bindings) {
+                    self.push_let_binding(symbol_name, binding);
                 }
-                let result = self.process_expr(expr);
+                let result = self.process_tail_expr(expr, tail);
                 self.pop_let_context();
                 result
             }
-            Expr::Symbol(name) => self
-                .resolve_symbol(name)
-                .expect("Symbol doesn't exist")
-                .clone(),
+            Expr::LetStarProcedure(symbols, expr) => {
+                // Each binding is visible from the following init expressions
+                self.push_let_context();
+                for (i, (symbol_name, sym_expr)) in symbols.iter().enumerate() {
+                    let assigned = symbols[i + 1..]
+                        .iter()
+                        .map(|(_, next_expr)| next_expr)
+                        .chain(std::iter::once(expr.as_ref()))
+                        .any(|scope_expr| is_assigned(symbol_name, scope_expr));
+                    let binding = self.process_binding(symbol_name, sym_expr, assigned);
+                    self.push_let_binding(symbol_name, binding);
+                }
+                let result = self.process_tail_expr(expr, tail);
+                self.pop_let_context();
+                result
+            }
+            Expr::LetrecProcedure(symbols, expr) | Expr::LetrecStarProcedure(symbols, expr) => {
+                self.build_letrec(symbols, expr, tail)
+            }
+            Expr::Lambda(args, body) => {
+                let captures = self.procedure_captures(args.symbols().cloned().collect(), body);
+                let function_name = format!("lambda.{}", self.alloc_id);
+                self.build_procedure(&function_name, args, &captures, body);
+                self.build_closure(&function_name, &captures)
+            }
+            Expr::List(items) => match items.split_first() {
+                Some((operator, operands)) => self.build_closure_call(operator, operands, tail),
+                None => unimplemented!("Cannot process this token yet {:?}", expr),
+            },
+            Expr::NamedLetProcedure(name, symbols, body) => {
+                self.build_named_let(name, symbols, body, tail)
+            }
+            Expr::Symbol(name) => match self.resolve_symbol(name).cloned() {
+                Some(Binding::Procedure(procedure)) => {
+                    self.build_closure(&procedure.function_name, &procedure.captures)
+                }
+                None if RUNTIME_PROCEDURES.contains(&name.as_str()) => {
+                    self.build_primitive_closure(name)
+                }
+                _ => self.resolve_value(name),
+            },
             Expr::DefineExpr(name, value) => {
-                // I'm not sure what to do with these
                 let alloc = self.process_expr(value);
-                self.push_let_binding(name, alloc.clone());
+                let binding = if self.symbol_scopes.len() > 1 {
+                    // Following expressions may assign the variable, so it's always boxed
+                    Binding::Boxed(self.build_box(&alloc))
+                } else {
+                    let global = Binding::Global(name.clone());
+                    self.assign(&global, &alloc);
+                    global
+                };
+                self.push_let_binding(name, binding);
                 alloc
             }
-            Expr::DefineProcedure(name, args, body) => {
-                self.ir_buffer
-                    .push(CompilispIr::StartProcedure(name.clone()));
-                // Workaround to reuse let scopes for symbol resolutions
-                self.push_let_context();
-                self.ir_buffer
-                    .push(CompilispIr::MapProcedureArgs(args.clone(), self.alloc_id));
-                for symbol_name in args {
-                    self.alloc_id += 1;
-                    let alloc = Alloc {
-                        alloc_type: AllocType::Int,
-                        id: self.alloc_id,
-                    };
-                    self.push_let_binding(symbol_name, alloc);
+            Expr::Set(name, value) => {
+                let value = self.process_expr(value);
+                match self.resolve_symbol(name).cloned() {
+                    // Assigned procedures are always bound as values or globals
+                    Some(binding) => self.assign(&binding, &value),
+                    None => self
+                        .errors
+                        .push(CompilispError::UnboundVariable(name.clone())),
                 }
-                let result = self.process_expr(body);
-                self.ir_buffer.push(CompilispIr::EndProcedure(result.id));
-                self.pop_let_context();
-                result
+                value
+            }
+            Expr::DefineProcedure(name, args, body)
+                if self.symbol_scopes.len() == 1
+                    && matches!(self.resolve_symbol(name), Some(Binding::Global(_))) =>
+            {
+                self.build_procedure(name, args, &[], body);
+                let closure = self.build_closure(name, &[]);
+                self.assign(&Binding::Global(name.clone()), &closure);
+                closure
+            }
+            Expr::DefineProcedure(name, args, body) => {
+                // Top level procedures are visible from anywhere, so they can't capture
+                let captures = if self.symbol_scopes.len() > 1 {
+                    let mut bound = args.symbols().cloned().collect::<Vec<_>>();
+                    bound.push(name.clone());
+                    self.procedure_captures(bound, body)
+                } else {
+                    vec![]
+                };
+                let function_name = if captures.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{name}.{}", self.alloc_id)
+                };
+                let procedure = LocalProcedure {
+                    function_name: function_name.clone(),
+                    captures: captures.clone(),
+                };
+                self.push_let_binding(name, Binding::Procedure(procedure));
+                self.build_procedure(&function_name, args, &captures, body)
+            }
+            Expr::Do(variables, test, result, body) => {
+                self.build_do_loop(variables, test, result, body, tail)
             }
             _ => {
                 unimplemented!("Cannot process this token yet {:?}", expr)
@@ -153,36 +545,482 @@ impl CompilispIrGenerator {
         }
     }
 
-    fn build_if_call(&mut self, args: &[Expr]) -> Alloc {
+    /// Evaluates the expressions in order, the last one gives the value and is in tail position
+    fn build_sequence(&mut self, exprs: &[Expr], tail: bool) -> Alloc {
+        let Some((last, init)) = exprs.split_last() else {
+            return self.build_unspecified();
+        };
+        for expr in init {
+            self.process_expr(expr);
+        }
+        self.process_tail_expr(last, tail)
+    }
+
+    fn build_if_call(&mut self, args: &[Expr], tail: bool) -> Alloc {
+        if !(2..=3).contains(&args.len()) {
+            self.errors
+                .push(CompilispError::IllFormedSyntax("if".to_owned()));
+            return self.build_unspecified();
+        }
         let cond_expr = &args[0];
         // if(cond_expr)
         let cond_alloc = self.process_expr(cond_expr);
+        // The condition may be a variable, so the result is stored apart
+        self.alloc_id += 1;
+        let if_alloc_id = self.alloc_id;
+        self.ir_buffer.push(CompilispIr::DeclareVariable {
+            alloc_id: if_alloc_id,
+        });
         self.ir_buffer.push(CompilispIr::IfExpressionEval {
             cond_alloc: cond_alloc.id,
+            known_boolean: cond_alloc.alloc_type == AllocType::Bool,
         });
         // then {
         let then_expr = &args[1];
-        let res = self.process_expr(then_expr);
+        let then_res = self.process_tail_expr(then_expr, tail);
         self.ir_buffer.push(CompilispIr::IfExpressionEndThen {
-            result_alloc: res.id,
-            cond_alloc: cond_alloc.id,
+            result_alloc: then_res.id,
+            if_alloc: if_alloc_id,
         });
         // } else {
-        if let Some(else_expr) = args.get(2) {
-            self.ir_buffer.push(CompilispIr::IfExpressionElse);
-            let res = self.process_expr(else_expr);
-            self.ir_buffer.push(CompilispIr::IfExpressionEndElse {
-                result_alloc: res.id,
-                cond_alloc: cond_alloc.id,
-            });
-        }
+        self.ir_buffer.push(CompilispIr::IfExpressionElse);
+        let else_res = match args.get(2) {
+            Some(else_expr) => self.process_tail_expr(else_expr, tail),
+            None => self.build_unspecified(),
+        };
+        self.ir_buffer.push(CompilispIr::IfExpressionEndElse {
+            result_alloc: else_res.id,
+            if_alloc: if_alloc_id,
+        });
         // } finally
         self.ir_buffer.push(CompilispIr::IfExpressionEndBlock);
 
-        cond_alloc
+        // Branches of different types give a value only known at runtime
+        let alloc_type = if then_res.alloc_type == else_res.alloc_type {
+            then_res.alloc_type
+        } else {
+            AllocType::Object
+        };
+        Alloc {
+            alloc_type,
+            id: if_alloc_id,
+        }
+    }
+
+    fn build_unspecified(&mut self) -> Alloc {
+        self.alloc_id += 1;
+        self.ir_buffer.push(CompilispIr::ConstUnspecified {
+            alloc_id: self.alloc_id,
+        });
+        Alloc {
+            id: self.alloc_id,
+            alloc_type: AllocType::Unspecified,
+        }
+    }
+
+    fn build_do_loop(
+        &mut self,
+        variables: &[(String, Expr, Option<Expr>)],
+        test: &Expr,
+        result: &[Expr],
+        body: &[Expr],
+        tail: bool,
+    ) -> Alloc {
+        let init_values = variables
+            .iter()
+            .map(|(_, init, _)| self.process_expr(init))
+            .collect::<Vec<_>>();
+        self.push_let_context();
+        let mut variable_allocs = vec![];
+        for ((symbol_name, _, step), init_value) in variables.iter().zip(init_values) {
+            self.alloc_id += 1;
+            // Steps may change the type of the variable
+            let alloc_type = match step {
+                Some(_) => AllocType::Object,
+                None => init_value.alloc_type,
+            };
+            let alloc = Alloc {
+                alloc_type,
+                id: self.alloc_id,
+            };
+            self.ir_buffer.push(CompilispIr::DefineVariable {
+                alloc_id: alloc.id,
+                value_alloc: init_value.id,
+            });
+            variable_allocs.push(alloc.id);
+            self.push_let_binding(symbol_name, Binding::Value(alloc));
+        }
+        // while !test {
+        self.ir_buffer.push(CompilispIr::LoopHeader);
+        let cond_alloc = self.process_expr(test);
+        self.ir_buffer.push(CompilispIr::LoopBody {
+            cond_alloc: cond_alloc.id,
+            known_boolean: cond_alloc.alloc_type == AllocType::Bool,
+        });
+        for expr in body {
+            self.process_expr(expr);
+        }
+        // All steps are evaluated before updating any variable
+        let mut step_values = vec![];
+        for ((_, _, step), alloc_id) in variables.iter().zip(variable_allocs) {
+            if let Some(step) = step {
+                step_values.push((alloc_id, self.process_expr(step).id));
+            }
+        }
+        for (alloc_id, value_alloc) in step_values {
+            self.ir_buffer.push(CompilispIr::SetVariable {
+                alloc_id,
+                value_alloc,
+            });
+        }
+        self.ir_buffer.push(CompilispIr::LoopBackEdge);
+        // }
+        let mut result_alloc = cond_alloc;
+        for (i, expr) in result.iter().enumerate() {
+            result_alloc = self.process_tail_expr(expr, tail && i == result.len() - 1);
+        }
+        self.pop_let_context();
+        result_alloc
+    }
+
+    /// Self evaluating data evaluate to themselves, symbols are built as constants and lists
+    /// and vectors are built from their quoted items
+    fn build_quoted(&mut self, datum: &Expr) -> Alloc {
+        match datum {
+            Expr::Symbol(name) => {
+                self.alloc_id += 1;
+                self.ir_buffer.push(CompilispIr::GlobalSymbol {
+                    alloc_id: self.alloc_id,
+                    value: name.clone(),
+                });
+                Alloc {
+                    id: self.alloc_id,
+                    alloc_type: AllocType::Symbol,
+                }
+            }
+            Expr::Number(_)
+            | Expr::Real(_)
+            | Expr::Bignum(_)
+            | Expr::Boolean(_)
+            | Expr::Char(_)
+            | Expr::String(_) => self.process_expr(datum),
+            // Dotted lists cons their items onto the datum after the dot
+            Expr::List(items) if is_dotted(items) => {
+                let (last, init) = items.split_last().unwrap();
+                let mut list = self.build_quoted(last);
+                for item in init[..init.len() - 1].iter().rev() {
+                    self.alloc_id += 1;
+                    let return_alloc_id = self.alloc_id;
+                    self.ir_buffer
+                        .push(CompilispIr::ProcedureReturnValue(return_alloc_id));
+                    self.ir_buffer.push(CompilispIr::ProcedureScopeStart);
+                    let item = self.build_quoted(item);
+                    list = self.build_runtime_call("cons", vec![item, list], return_alloc_id);
+                }
+                list
+            }
+            // Quoted lists are built when evaluated, as they can't be modified
+            Expr::List(items) => {
+                self.alloc_id += 1;
+                let return_alloc_id = self.alloc_id;
+                self.ir_buffer
+                    .push(CompilispIr::ProcedureReturnValue(return_alloc_id));
+                self.ir_buffer.push(CompilispIr::ProcedureScopeStart);
+                let items = items.iter().map(|item| self.build_quoted(item)).collect();
+                self.build_runtime_call("list", items, return_alloc_id)
+            }
+            Expr::Vector(items) => {
+                self.alloc_id += 1;
+                let return_alloc_id = self.alloc_id;
+                self.ir_buffer
+                    .push(CompilispIr::ProcedureReturnValue(return_alloc_id));
+                self.ir_buffer.push(CompilispIr::ProcedureScopeStart);
+                let items = items.iter().map(|item| self.build_quoted(item)).collect();
+                self.build_runtime_call("vector", items, return_alloc_id)
+            }
+            _ => unimplemented!("Cannot quote this datum yet {:?}", datum),
+        }
+    }
+
+    /// Lambda expressions are bound as local procedures, any other expression is evaluated.
+    /// Assigned bindings are always evaluated, since local procedures can't be replaced
+    fn process_binding(&mut self, name: &str, expr: &Expr, assigned: bool) -> Binding {
+        match expr {
+            Expr::Lambda(args, body) if !assigned => {
+                let captures = self.procedure_captures(args.symbols().cloned().collect(), body);
+                let function_name = format!("{name}.{}", self.alloc_id);
+                self.build_procedure(&function_name, args, &captures, body);
+                Binding::Procedure(LocalProcedure {
+                    function_name,
+                    captures,
+                })
+            }
+            _ => {
+                let value = self.process_expr(expr);
+                if assigned {
+                    Binding::Boxed(self.build_box(&value))
+                } else {
+                    Binding::Value(value)
+                }
+            }
+        }
+    }
+
+    /// All bindings are visible from every init expression, so local procedures can be mutually
+    /// recursive. Init expressions are evaluated from left to right, as `letrec*` requires
+    fn build_letrec(&mut self, symbols: &[(String, Expr)], body: &Expr, tail: bool) -> Alloc {
+        self.push_let_context();
+        let assigned = |symbol_name: &str| {
+            is_assigned(symbol_name, body)
+                || symbols
+                    .iter()
+                    .any(|(_, sym_expr)| is_assigned(symbol_name, sym_expr))
+        };
+        // Values are declared first, so procedures can capture them
+        let mut values = vec![];
+        for (symbol_name, sym_expr) in symbols {
+            if !matches!(sym_expr, Expr::Lambda(..)) || assigned(symbol_name) {
+                self.alloc_id += 1;
+                let alloc = Alloc {
+                    alloc_type: AllocType::Object,
+                    id: self.alloc_id,
+                };
+                self.ir_buffer
+                    .push(CompilispIr::DeclareVariable { alloc_id: alloc.id });
+                let binding = if assigned(symbol_name) {
+                    Binding::Boxed(self.build_box(&alloc))
+                } else {
+                    Binding::Value(alloc)
+                };
+                values.push((binding.clone(), sym_expr));
+                self.push_let_binding(symbol_name, binding);
+            }
+        }
+        let procedures = symbols
+            .iter()
+            .filter_map(|(symbol_name, sym_expr)| match sym_expr {
+                Expr::Lambda(args, body) if !assigned(symbol_name) => {
+                    Some((symbol_name, args, body))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // Procedures may call each other, so captures are shared by the whole group
+        let mut captures: Vec<String> = vec![];
+        for (_, args, body) in &procedures {
+            let mut bound = args.symbols().cloned().collect::<Vec<_>>();
+            bound.extend(procedures.iter().map(|(name, _, _)| name.to_string()));
+            for capture in self.procedure_captures(bound, body) {
+                if !captures.contains(&capture) {
+                    captures.push(capture);
+                }
+            }
+        }
+        let mut function_names = vec![];
+        for (name, _, _) in &procedures {
+            let function_name = format!("{name}.{}", self.alloc_id);
+            let procedure = LocalProcedure {
+                function_name: function_name.clone(),
+                captures: captures.clone(),
+            };
+            self.push_let_binding(name, Binding::Procedure(procedure));
+            function_names.push(function_name);
+        }
+        for ((_, args, body), function_name) in procedures.iter().zip(function_names) {
+            self.build_procedure(&function_name, args, &captures, body);
+        }
+        for (binding, sym_expr) in values {
+            let value = self.process_expr(sym_expr);
+            self.assign(&binding, &value);
+        }
+        let result = self.process_tail_expr(body, tail);
+        self.pop_let_context();
+        result
+    }
+
+    /// Named let is lowered to a local procedure, called with the initial values of its bindings
+    fn build_named_let(
+        &mut self,
+        name: &str,
+        symbols: &[(String, Expr)],
+        body: &Expr,
+        tail: bool,
+    ) -> Alloc {
+        let args = Formals::new(
+            symbols
+                .iter()
+                .map(|(symbol_name, _)| symbol_name.clone())
+                .collect(),
+        );
+        if is_assigned(name, body) {
+            // Assigned loops are called as `((letrec ((name (lambda args body))) name) inits)`,
+            // so the procedure is a boxed closure like assigned letrec bindings
+            let lambda = Expr::Lambda(args, Box::new(body.clone()));
+            let letrec = Expr::LetrecProcedure(
+                vec![(name.to_owned(), lambda)],
+                Box::new(Expr::Symbol(name.to_owned())),
+            );
+            let inits = symbols
+                .iter()
+                .map(|(_, sym_expr)| sym_expr.clone())
+                .collect::<Vec<_>>();
+            return self.build_closure_call(&letrec, &inits, tail);
+        }
+        let init_values = symbols
+            .iter()
+            .map(|(_, sym_expr)| self.process_expr(sym_expr))
+            .collect::<Vec<_>>();
+
+        let mut bound = args.args.clone();
+        bound.push(name.to_owned());
+        let captures = self.procedure_captures(bound, body);
+        let function_name = format!("{name}.{}", self.alloc_id);
+        let procedure = LocalProcedure {
+            function_name: function_name.clone(),
+            captures: captures.clone(),
+        };
+        self.push_let_context();
+        self.push_let_binding(name, Binding::Procedure(procedure));
+        self.build_procedure(&function_name, &args, &captures, body);
+
+        self.alloc_id += 1;
+        let return_alloc_id = self.alloc_id;
+        self.ir_buffer
+            .push(CompilispIr::ProcedureReturnValue(return_alloc_id));
+        self.ir_buffer.push(CompilispIr::ProcedureScopeStart);
+        let result = self.build_call(name, init_values, return_alloc_id, tail);
+        self.pop_let_context();
+        result
+    }
+
+    /// Emits the procedure into the procedures buffer, keeping the current ir buffer untouched
+    fn build_procedure(
+        &mut self,
+        name: &str,
+        args: &Formals,
+        captures: &[String],
+        body: &Expr,
+    ) -> Alloc {
+        // Captured boxes are shared with the enclosing scope
+        let boxed_captures = captures
+            .iter()
+            .map(|capture| matches!(self.resolve_symbol(capture), Some(Binding::Boxed(_))))
+            .collect::<Vec<_>>();
+        let caller_buffer = std::mem::take(&mut self.ir_buffer);
+        self.ir_buffer
+            .push(CompilispIr::StartProcedure(name.to_owned()));
+        // Workaround to reuse let scopes for symbol resolutions
+        self.push_let_context();
+        self.ir_buffer
+            .push(CompilispIr::MapProcedureArgs(args.clone(), self.alloc_id));
+        let arg_allocs = args
+            .symbols()
+            .map(|_| {
+                self.alloc_id += 1;
+                Alloc {
+                    alloc_type: AllocType::Object,
+                    id: self.alloc_id,
+                }
+            })
+            .collect::<Vec<_>>();
+        for (symbol_name, alloc) in args.symbols().zip(arg_allocs) {
+            let binding = if is_assigned(symbol_name, body) {
+                Binding::Boxed(self.build_box(&alloc))
+            } else {
+                Binding::Value(alloc)
+            };
+            self.push_let_binding(symbol_name, binding);
+        }
+        self.ir_buffer.push(CompilispIr::MapProcedureEnv(
+            captures.to_vec(),
+            self.alloc_id,
+        ));
+        for (symbol_name, boxed) in captures.iter().zip(boxed_captures) {
+            self.alloc_id += 1;
+            let alloc = Alloc {
+                alloc_type: AllocType::Object,
+                id: self.alloc_id,
+            };
+            let binding = if boxed {
+                Binding::Boxed(alloc)
+            } else {
+                Binding::Value(alloc)
+            };
+            self.push_let_binding(symbol_name, binding);
+        }
+        let result = self.process_tail_expr(body, true);
+        self.ir_buffer.push(CompilispIr::EndProcedure(result.id));
+        self.pop_let_context();
+        let mut procedure_ir = std::mem::replace(&mut self.ir_buffer, caller_buffer);
+        self.procedures_buffer.append(&mut procedure_ir);
+        result
+    }
+
+    /// Closure of a module function calling the runtime procedure, built the first time the
+    /// primitive is used as a value
+    fn build_primitive_closure(&mut self, name: &str) -> Alloc {
+        let function_name = format!("primitive.{name}");
+        if !self
+            .wrapped_primitives
+            .iter()
+            .any(|wrapped| wrapped == name)
+        {
+            self.wrapped_primitives.push(name.to_owned());
+            self.alloc_id += 1;
+            let return_id = self.alloc_id;
+            self.procedures_buffer.extend([
+                CompilispIr::StartProcedure(function_name.clone()),
+                CompilispIr::ProcedureReturnValue(return_id),
+                CompilispIr::ForwardRuntimeCall {
+                    name: name.to_owned(),
+                    return_id,
+                },
+                CompilispIr::EndProcedure(return_id),
+            ]);
+        }
+        self.build_closure(&function_name, &[])
+    }
+
+    /// Closure values keep a copy of the captured values, or the box of assigned ones
+    fn build_closure(&mut self, function_name: &str, captures: &[String]) -> Alloc {
+        let env = captures
+            .iter()
+            .map(|capture| self.resolve_storage(capture))
+            .collect();
+        self.alloc_id += 1;
+        self.ir_buffer.push(CompilispIr::MakeClosure {
+            alloc_id: self.alloc_id,
+            function_name: function_name.to_owned(),
+            env,
+        });
+        Alloc {
+            id: self.alloc_id,
+            alloc_type: AllocType::Procedure,
+        }
+    }
+
+    /// Local symbols referenced by a procedure body, that must be captured in its environment
+    fn procedure_captures(&self, mut bound: Vec<String>, body: &Expr) -> Vec<String> {
+        let mut free = vec![];
+        collect_free_symbols(body, &mut bound, &mut free);
+
+        let mut captures: Vec<String> = vec![];
+        for symbol_name in free {
+            let symbol_captures = match self.resolve_symbol(&symbol_name) {
+                Some(Binding::Value(_) | Binding::Boxed(_)) => vec![symbol_name],
+                Some(Binding::Procedure(procedure)) => procedure.captures.clone(),
+                Some(Binding::Global(_)) | None => vec![],
+            };
+            for capture in symbol_captures {
+                if !captures.contains(&capture) {
+                    captures.push(capture);
+                }
+            }
+        }
+        captures
     }
 
-    fn build_generic_call(&mut self, name: &str, args: &Vec<Expr>) -> Alloc {
+    fn build_generic_call(&mut self, name: &str, args: &Vec<Expr>, tail: bool) -> Alloc {
         self.alloc_id += 1;
         let return_alloc_id = self.alloc_id;
         let mut call_args = vec![];
@@ -193,20 +1031,109 @@ impl CompilispIrGenerator {
             let alloc_id = self.process_expr(arg);
             call_args.push(alloc_id);
         }
-        let name = name.to_owned();
-        self.ir_buffer.push(CompilispIr::CallProcedure {
-            name,
+        self.build_call(name, call_args, return_alloc_id, tail)
+    }
+
+    /// Calls an expression evaluating to a procedure
+    fn build_closure_call(&mut self, operator: &Expr, operands: &[Expr], tail: bool) -> Alloc {
+        self.alloc_id += 1;
+        let return_alloc_id = self.alloc_id;
+        self.ir_buffer
+            .push(CompilispIr::ProcedureReturnValue(return_alloc_id));
+        self.ir_buffer.push(CompilispIr::ProcedureScopeStart);
+        let closure_alloc = self.process_expr(operator);
+        let args = operands
+            .iter()
+            .map(|operand| self.process_expr(operand))
+            .collect();
+        self.ir_buffer.push(CompilispIr::CallClosure {
+            closure_alloc: closure_alloc.id,
             return_id: return_alloc_id,
-            args: call_args,
+            args,
+            tail,
         });
         self.ir_buffer.push(CompilispIr::ProcedureScopeEnd);
         Alloc {
             id: return_alloc_id,
-            alloc_type: AllocType::Int,
+            alloc_type: AllocType::Object,
         }
     }
 
-    fn push_let_binding(&mut self, bind_name: &str, bind_value: Alloc) {
+    /// Calls in tail position reuse the caller frame, so the stack doesn't grow on tail recursion
+    fn build_call(
+        &mut self,
+        name: &str,
+        args: Vec<Alloc>,
+        return_alloc_id: AllocId,
+        tail: bool,
+    ) -> Alloc {
+        let alloc_type = match self.resolve_symbol(name).cloned() {
+            Some(Binding::Procedure(procedure)) => {
+                let env = procedure
+                    .captures
+                    .iter()
+                    .map(|capture| self.resolve_storage(capture))
+                    .collect();
+                self.ir_buffer.push(CompilispIr::CallProcedure {
+                    name: procedure.function_name,
+                    return_id: return_alloc_id,
+                    args,
+                    env,
+                    tail,
+                });
+                AllocType::Object
+            }
+            Some(Binding::Value(_) | Binding::Boxed(_) | Binding::Global(_)) => {
+                let closure_alloc = self.resolve_value(name);
+                self.ir_buffer.push(CompilispIr::CallClosure {
+                    closure_alloc: closure_alloc.id,
+                    return_id: return_alloc_id,
+                    args,
+                    tail,
+                });
+                AllocType::Object
+            }
+            None if RUNTIME_PROCEDURES.contains(&name) => {
+                return self.build_runtime_call(name, args, return_alloc_id)
+            }
+            None => {
+                self.errors
+                    .push(CompilispError::UnboundVariable(name.to_owned()));
+                AllocType::Object
+            }
+        };
+        self.ir_buffer.push(CompilispIr::ProcedureScopeEnd);
+        Alloc {
+            id: return_alloc_id,
+            alloc_type,
+        }
+    }
+
+    /// Calls the runtime procedure even if the module binds its name
+    fn build_runtime_call(
+        &mut self,
+        name: &str,
+        args: Vec<Alloc>,
+        return_alloc_id: AllocId,
+    ) -> Alloc {
+        self.ir_buffer.push(CompilispIr::CallRuntime {
+            name: name.to_owned(),
+            return_id: return_alloc_id,
+            args,
+        });
+        self.ir_buffer.push(CompilispIr::ProcedureScopeEnd);
+        let alloc_type = if BOOLEAN_PRIMITIVES.contains(&name) {
+            AllocType::Bool
+        } else {
+            AllocType::Object
+        };
+        Alloc {
+            id: return_alloc_id,
+            alloc_type,
+        }
+    }
+
+    fn push_let_binding(&mut self, bind_name: &str, bind_value: Binding) {
         if let Some(scope) = self.symbol_scopes.last_mut() {
             scope.insert(bind_name.to_owned(), bind_value);
         }
@@ -219,11 +1146,290 @@ impl CompilispIrGenerator {
         self.symbol_scopes.pop();
     }
 
-    fn resolve_symbol(&self, symbol_name: &str) -> Option<&Alloc> {
+    fn resolve_symbol(&self, symbol_name: &str) -> Option<&Binding> {
         self.symbol_scopes
             .iter()
             .rev()
             .flat_map(|scope| scope.get(symbol_name))
             .next()
     }
+
+    fn resolve_value(&mut self, symbol_name: &str) -> Alloc {
+        match self.resolve_symbol(symbol_name).cloned() {
+            Some(Binding::Boxed(box_alloc)) => {
+                self.alloc_id += 1;
+                self.ir_buffer.push(CompilispIr::Unbox {
+                    alloc_id: self.alloc_id,
+                    box_alloc: box_alloc.id,
+                });
+                Alloc {
+                    id: self.alloc_id,
+                    alloc_type: AllocType::Object,
+                }
+            }
+            Some(Binding::Global(name)) => {
+                self.alloc_id += 1;
+                self.ir_buffer.push(CompilispIr::LoadGlobal {
+                    alloc_id: self.alloc_id,
+                    name,
+                });
+                Alloc {
+                    id: self.alloc_id,
+                    alloc_type: AllocType::Object,
+                }
+            }
+            None => {
+                self.errors
+                    .push(CompilispError::UnboundVariable(symbol_name.to_owned()));
+                self.build_unspecified()
+            }
+            _ => self.resolve_storage(symbol_name),
+        }
+    }
+
+    /// Allocation holding the symbol value, or its box if it's assigned
+    fn resolve_storage(&self, symbol_name: &str) -> Alloc {
+        match self.resolve_symbol(symbol_name) {
+            Some(Binding::Value(alloc) | Binding::Boxed(alloc)) => alloc.clone(),
+            _ => panic!("Symbol doesn't exist"),
+        }
+    }
+
+    fn build_box(&mut self, value: &Alloc) -> Alloc {
+        self.alloc_id += 1;
+        self.ir_buffer.push(CompilispIr::MakeBox {
+            alloc_id: self.alloc_id,
+            value_alloc: value.id,
+        });
+        Alloc {
+            id: self.alloc_id,
+            alloc_type: AllocType::Object,
+        }
+    }
+
+    fn assign(&mut self, binding: &Binding, value: &Alloc) {
+        match binding {
+            Binding::Value(alloc) => self.ir_buffer.push(CompilispIr::SetVariable {
+                alloc_id: alloc.id,
+                value_alloc: value.id,
+            }),
+            Binding::Boxed(box_alloc) => self.ir_buffer.push(CompilispIr::SetBox {
+                box_alloc: box_alloc.id,
+                value_alloc: value.id,
+            }),
+            Binding::Global(name) => self.ir_buffer.push(CompilispIr::SetGlobal {
+                name: name.clone(),
+                value_alloc: value.id,
+            }),
+            Binding::Procedure(_) => unreachable!("Local procedures are never assigned"),
+        }
+    }
+}
+
+/// Whether a quoted list is written with a dot before its last datum
+fn is_dotted(items: &[Expr]) -> bool {
+    items.len() >= 3 && matches!(&items[items.len() - 2], Expr::Symbol(dot) if dot == ".")
+}
+
+/// Collects symbols referenced in `expr` that are not in `bound` nor bound by `expr` itself
+fn collect_free_symbols(expr: &Expr, bound: &mut Vec<String>, free: &mut Vec<String>) {
+    let bound_len = bound.len();
+    match expr {
+        Expr::Symbol(name) => {
+            if !bound.contains(name) && !free.contains(name) {
+                free.push(name.clone());
+            }
+        }
+        Expr::Procedure(name, args) => {
+            if !bound.contains(name) && !free.contains(name) {
+                free.push(name.clone());
+            }
+            for arg in args {
+                collect_free_symbols(arg, bound, free);
+            }
+        }
+        Expr::List(items) => {
+            for item in items {
+                collect_free_symbols(item, bound, free);
+            }
+        }
+        Expr::LetProcedure(symbols, body) => {
+            for (_, sym_expr) in symbols {
+                collect_free_symbols(sym_expr, bound, free);
+            }
+            bound.extend(symbols.iter().map(|(symbol_name, _)| symbol_name.clone()));
+            collect_free_symbols(body, bound, free);
+        }
+        Expr::LetStarProcedure(symbols, body) => {
+            for (symbol_name, sym_expr) in symbols {
+                collect_free_symbols(sym_expr, bound, free);
+                bound.push(symbol_name.clone());
+            }
+            collect_free_symbols(body, bound, free);
+        }
+        Expr::LetrecProcedure(symbols, body) | Expr::LetrecStarProcedure(symbols, body) => {
+            bound.extend(symbols.iter().map(|(symbol_name, _)| symbol_name.clone()));
+            for (_, sym_expr) in symbols {
+                collect_free_symbols(sym_expr, bound, free);
+            }
+            collect_free_symbols(body, bound, free);
+        }
+        Expr::Lambda(args, body) => {
+            bound.extend(args.symbols().cloned());
+            collect_free_symbols(body, bound, free);
+        }
+        Expr::NamedLetProcedure(name, symbols, body) => {
+            for (_, sym_expr) in symbols {
+                collect_free_symbols(sym_expr, bound, free);
+            }
+            bound.push(name.clone());
+            bound.extend(symbols.iter().map(|(symbol_name, _)| symbol_name.clone()));
+            collect_free_symbols(body, bound, free);
+        }
+        Expr::DefineExpr(_, value) => collect_free_symbols(value, bound, free),
+        Expr::Set(name, value) => {
+            if !bound.contains(name) && !free.contains(name) {
+                free.push(name.clone());
+            }
+            collect_free_symbols(value, bound, free);
+        }
+        Expr::Do(variables, test, result, body) => {
+            for (_, init, _) in variables {
+                collect_free_symbols(init, bound, free);
+            }
+            bound.extend(
+                variables
+                    .iter()
+                    .map(|(symbol_name, _, _)| symbol_name.clone()),
+            );
+            for (_, _, step) in variables {
+                if let Some(step) = step {
+                    collect_free_symbols(step, bound, free);
+                }
+            }
+            for expr in std::iter::once(test.as_ref()).chain(result).chain(body) {
+                collect_free_symbols(expr, bound, free);
+            }
+        }
+        Expr::DefineProcedure(name, args, body) => {
+            bound.push(name.clone());
+            bound.extend(args.symbols().cloned());
+            collect_free_symbols(body, bound, free);
+        }
+        Expr::Number(_)
+        | Expr::Real(_)
+        | Expr::Bignum(_)
+        | Expr::Boolean(_)
+        | Expr::Char(_)
+        | Expr::String(_)
+        | Expr::Quote(_)
+        | Expr::Vector(_)
+        | Expr::Error => {}
+        Expr::Cond(..)
+        | Expr::Case(..)
+        | Expr::And(_)
+        | Expr::Or(_)
+        | Expr::When(..)
+        | Expr::Unless(..) => {
+            unreachable!("Derived expressions are desugared before ir generation")
+        }
+        Expr::Quasiquote(_)
+        | Expr::DefineSyntax(..)
+        | Expr::LetSyntax(..)
+        | Expr::LetrecSyntax(..) => {
+            unreachable!("Macros are expanded before ir generation")
+        }
+    }
+    bound.truncate(bound_len);
+}
+
+/// Whether `expr` assigns the symbol. Shadowing bindings aren't considered, so it may report
+/// assignments to other variables with the same name
+fn is_assigned(symbol_name: &str, expr: &Expr) -> bool {
+    match expr {
+        Expr::Set(name, value) => name == symbol_name || is_assigned(symbol_name, value),
+        Expr::Procedure(_, exprs) | Expr::List(exprs) => {
+            exprs.iter().any(|expr| is_assigned(symbol_name, expr))
+        }
+        Expr::LetProcedure(symbols, body)
+        | Expr::NamedLetProcedure(_, symbols, body)
+        | Expr::LetStarProcedure(symbols, body)
+        | Expr::LetrecProcedure(symbols, body)
+        | Expr::LetrecStarProcedure(symbols, body) => {
+            symbols
+                .iter()
+                .any(|(_, sym_expr)| is_assigned(symbol_name, sym_expr))
+                || is_assigned(symbol_name, body)
+        }
+        Expr::Lambda(_, body) | Expr::DefineExpr(_, body) | Expr::DefineProcedure(_, _, body) => {
+            is_assigned(symbol_name, body)
+        }
+        Expr::Do(variables, test, result, body) => {
+            variables.iter().any(|(_, init, step)| {
+                is_assigned(symbol_name, init)
+                    || step
+                        .as_ref()
+                        .is_some_and(|step| is_assigned(symbol_name, step))
+            }) || std::iter::once(test.as_ref())
+                .chain(result)
+                .chain(body)
+                .any(|expr| is_assigned(symbol_name, expr))
+        }
+        Expr::Number(_)
+        | Expr::Real(_)
+        | Expr::Bignum(_)
+        | Expr::Boolean(_)
+        | Expr::Char(_)
+        | Expr::Symbol(_)
+        | Expr::String(_)
+        | Expr::Quote(_)
+        | Expr::Vector(_)
+        | Expr::Error => false,
+        Expr::Cond(..)
+        | Expr::Case(..)
+        | Expr::And(_)
+        | Expr::Or(_)
+        | Expr::When(..)
+        | Expr::Unless(..) => {
+            unreachable!("Derived expressions are desugared before ir generation")
+        }
+        Expr::Quasiquote(_)
+        | Expr::DefineSyntax(..)
+        | Expr::LetSyntax(..)
+        | Expr::LetrecSyntax(..) => {
+            unreachable!("Macros are expanded before ir generation")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: Vec<Expr>) -> Expr {
+        Expr::Procedure(name.to_owned(), args)
+    }
+
+    #[test]
+    fn rejects_if_with_wrong_operands() {
+        for args in [vec![Expr::Boolean(true)], vec![Expr::Number(1); 4]] {
+            let mut generator = CompilispIrGenerator::new();
+            generator.process(&call("if", args));
+            assert!(matches!(
+                generator.errors.as_slice(),
+                [CompilispError::IllFormedSyntax(keyword)] if keyword == "if"
+            ));
+        }
+    }
+
+    #[test]
+    fn types_mixed_branches_at_runtime() {
+        let mut generator = CompilispIrGenerator::new();
+        let args = vec![Expr::Boolean(true), Expr::Number(1), Expr::Boolean(false)];
+        let result = generator.process_expr(&call("if", args));
+        assert_eq!(result.alloc_type, AllocType::Object);
+        let args = vec![Expr::Boolean(true), Expr::Number(1), Expr::Number(2)];
+        let result = generator.process_expr(&call("if", args));
+        assert_eq!(result.alloc_type, AllocType::Int);
+    }
 }
@@ -1,29 +1,41 @@
+use crate::ast::Formals;
 use crate::backend::compilisp_ir::{AllocId, CompilispIr};
-use crate::backend::function_builder::FunctionBuilder;
 use crate::backend::function_factory::FunctionFactory;
 use crate::backend::llvm_builder::Builder;
 use crate::backend::procedure_call_builder::ProcedureCallBuilder;
-use crate::backend::runtime::{ELSE_STR, EMPTY_STR, FINALLY_STR, THEN_STR};
+use crate::backend::runtime::{
+    ARGS_STR, ARITY_ERROR_STR, ELSE_STR, EMPTY_STR, FINALLY_STR, LOOP_BODY_STR, LOOP_EXIT_STR,
+    LOOP_STR, THEN_STR,
+};
 use crate::backend::type_factory::{CompilispType, TypeFactory};
 use crate::backend::value_builder::Value::VariableString;
 use crate::backend::value_builder::{Value, ValueBuilder};
 use llvm_sys::core::*;
-use llvm_sys::prelude::{LLVMBasicBlockRef, LLVMBuilderRef, LLVMModuleRef, LLVMValueRef};
+use llvm_sys::prelude::{LLVMBasicBlockRef, LLVMBool, LLVMBuilderRef, LLVMModuleRef, LLVMValueRef};
+use llvm_sys::LLVMIntPredicate;
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::ffi::c_ulonglong;
+use std::ffi::{c_uint, CString};
 
 pub const NUMBER_DISCRIMINATOR: i32 = 0;
-pub const BOOLEAN_DISCRIMINATOR: c_ulonglong = 1;
+pub const BOOLEAN_DISCRIMINATOR: i32 = 1;
 pub const STR_DISCRIMINATOR: i32 = 2;
 pub const SYMBOL_DISCRIMINATOR: i32 = 3;
+pub const PROCEDURE_DISCRIMINATOR: i32 = 4;
+pub const UNSPECIFIED_DISCRIMINATOR: i32 = 9;
+pub const CHAR_DISCRIMINATOR: i32 = 10;
+pub const REAL_DISCRIMINATOR: i32 = 11;
 
 struct ConditionalBlock {
-    block_then: LLVMBasicBlockRef,
     block_else: Option<LLVMBasicBlockRef>,
     block_finally: LLVMBasicBlockRef,
 }
 
+struct LoopBlock {
+    block_header: LLVMBasicBlockRef,
+    block_exit: LLVMBasicBlockRef,
+}
+
 pub struct CompilispLLVMGenerator<'a> {
     module: LLVMModuleRef,
     builder: LLVMBuilderRef,
@@ -32,6 +44,7 @@ pub struct CompilispLLVMGenerator<'a> {
     type_factory: &'a TypeFactory,
     alloc_map: HashMap<AllocId, LLVMValueRef>,
     conditional_blocks: Vec<ConditionalBlock>,
+    loop_blocks: Vec<LoopBlock>,
     current_function: Option<LLVMValueRef>,
 }
 
@@ -45,6 +58,7 @@ impl<'a> CompilispLLVMGenerator<'a> {
         let value_builder = RefCell::new(ValueBuilder::default());
         let alloc_map = HashMap::new();
         let conditional_blocks = Vec::new();
+        let loop_blocks = Vec::new();
         let current_function = None;
         Self {
             module,
@@ -54,6 +68,7 @@ impl<'a> CompilispLLVMGenerator<'a> {
             type_factory,
             alloc_map,
             conditional_blocks,
+            loop_blocks,
             current_function,
         }
     }
@@ -63,6 +78,208 @@ impl<'a> CompilispLLVMGenerator<'a> {
         let mut value_builder = self.value_builder.borrow_mut();
         unsafe { value_builder.build_value(context, self.builder, value, self.type_factory) }
     }
+
+    /// Builds an `i1` that is false only for the `#f` object. The value of known booleans is
+    /// loaded directly, other objects are true unless they are tagged as booleans
+    unsafe fn build_condition(&self, cond_alloc: AllocId, known_boolean: bool) -> LLVMValueRef {
+        let builder = Builder::new(self.builder);
+        let context = LLVMGetModuleContext(self.module);
+        let cond_value = self.alloc_map.get(&cond_alloc).copied().unwrap();
+        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+
+        let value_attr_ptr = builder.gep(cond_value, object_type, &[0, 1]);
+        if known_boolean {
+            let bool_type = self.type_factory.get_type(CompilispType::BoolPtr);
+            let casted =
+                LLVMBuildBitCast(self.builder, value_attr_ptr, bool_type, EMPTY_STR.as_ptr());
+            return builder.load(LLVMInt1TypeInContext(context), casted);
+        }
+
+        // Values of other types may have any bits set, so the whole byte is compared
+        let char_type = self.type_factory.get_type(CompilispType::Char);
+        let casted = LLVMBuildBitCast(
+            self.builder,
+            value_attr_ptr,
+            self.type_factory.get_pointer(char_type),
+            EMPTY_STR.as_ptr(),
+        );
+        let value = builder.load(char_type, casted);
+        let zero = LLVMConstInt(char_type, 0, LLVMBool::from(false));
+        let is_true = LLVMBuildICmp(
+            self.builder,
+            LLVMIntPredicate::LLVMIntNE,
+            value,
+            zero,
+            EMPTY_STR.as_ptr(),
+        );
+
+        let type_attr_ptr = builder.gep(cond_value, object_type, &[0, 0]);
+        let int_type = self.type_factory.get_type(CompilispType::Int);
+        let type_value = builder.load(int_type, type_attr_ptr);
+        let boolean_type = self.build_value(&Value::ConstInt(BOOLEAN_DISCRIMINATOR));
+        let not_boolean = LLVMBuildICmp(
+            self.builder,
+            LLVMIntPredicate::LLVMIntNE,
+            type_value,
+            boolean_type,
+            EMPTY_STR.as_ptr(),
+        );
+        LLVMBuildOr(self.builder, not_boolean, is_true, EMPTY_STR.as_ptr())
+    }
+    /// Loads the pointer to the object stored in a box
+    unsafe fn build_boxed_pointer(&self, box_alloc: AllocId) -> LLVMValueRef {
+        let builder = Builder::new(self.builder);
+        let box_value = self.alloc_map.get(&box_alloc).copied().unwrap();
+        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+        let object_pointer = self.type_factory.get_pointer(object_type);
+
+        let value_attr_ptr = builder.gep(box_value, object_type, &[0, 1]);
+        let casted = LLVMBuildBitCast(
+            self.builder,
+            value_attr_ptr,
+            self.type_factory.get_pointer(object_pointer),
+            EMPTY_STR.as_ptr(),
+        );
+        builder.load(object_pointer, casted)
+    }
+
+    /// Global holding a top level variable. Procedures are built before the top level code, so
+    /// the first one referencing the variable declares it
+    unsafe fn get_or_add_global(&self, name: &str) -> LLVMValueRef {
+        let global_name = CString::new(format!("global.{name}")).unwrap();
+        let global = LLVMGetNamedGlobal(self.module, global_name.as_ptr());
+        if !global.is_null() {
+            return global;
+        }
+        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+        let global = LLVMAddGlobal(self.module, object_type, global_name.as_ptr());
+        LLVMSetInitializer(global, LLVMConstNull(object_type));
+        global
+    }
+
+    /// Raises a runtime error when a procedure receives fewer arguments than it requires, or
+    /// more of them if it doesn't have a rest parameter
+    unsafe fn build_arity_check(&self, argc: LLVMValueRef, args: &Formals) {
+        let builder = Builder::new(self.builder);
+        let context = LLVMGetModuleContext(self.module);
+        let required = self.build_value(&Value::ConstInt(args.args.len() as i32));
+        let variadic = self.build_value(&Value::ConstInt(args.rest.is_some() as i32));
+        let predicate = if args.rest.is_some() {
+            LLVMIntPredicate::LLVMIntULT
+        } else {
+            LLVMIntPredicate::LLVMIntNE
+        };
+        let wrong_arity =
+            LLVMBuildICmp(self.builder, predicate, argc, required, EMPTY_STR.as_ptr());
+
+        let block_error = LLVMCreateBasicBlockInContext(context, ARITY_ERROR_STR.as_ptr());
+        let block_args = LLVMCreateBasicBlockInContext(context, ARGS_STR.as_ptr());
+        builder.cond_br(wrong_arity, block_error, block_args);
+
+        builder.insert_and_position_block(block_error);
+        let (fn_ref, fn_argtypes) = self
+            .function_factory
+            .get("compilisp_arity_error")
+            .copied()
+            .unwrap();
+        let mut error_args = [argc, required, variadic];
+        LLVMBuildCall2(
+            self.builder,
+            fn_argtypes,
+            fn_ref,
+            error_args.as_mut_ptr(),
+            error_args.len() as c_uint,
+            EMPTY_STR.as_ptr(),
+        );
+        LLVMBuildUnreachable(self.builder);
+
+        builder.insert_and_position_block(block_args);
+    }
+
+    /// Collects the arguments after the fixed ones in a list
+    unsafe fn build_rest_list(
+        &mut self,
+        argc: LLVMValueRef,
+        argv: LLVMValueRef,
+        fixed_len: usize,
+        alloc_id: AllocId,
+    ) {
+        let builder = Builder::new(self.builder);
+        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+        let fixed_len_value = self.build_value(&Value::ConstInt(fixed_len as i32));
+        let rest_argv = builder.gep(argv, object_type, &[fixed_len]);
+        let rest_argc = LLVMBuildSub(self.builder, argc, fixed_len_value, EMPTY_STR.as_ptr());
+
+        let (fn_ref, fn_argtypes) = self
+            .function_factory
+            .get("compilisp_make_list")
+            .copied()
+            .unwrap();
+        let mut args = [rest_argv, rest_argc];
+        let list = LLVMBuildCall2(
+            self.builder,
+            fn_argtypes,
+            fn_ref,
+            args.as_mut_ptr(),
+            args.len() as c_uint,
+            EMPTY_STR.as_ptr(),
+        );
+        let alloca = builder.entry_alloca(object_type, &EMPTY_STR);
+        LLVMBuildStore(self.builder, list, alloca);
+        self.alloc_map.insert(alloc_id, alloca);
+    }
+
+    /// Builds an object calling the runtime function with the value as a global string
+    unsafe fn build_from_global_str(
+        &mut self,
+        fn_name: &str,
+        value: &str,
+        global_name: &str,
+        alloc_id: AllocId,
+    ) {
+        let builder = Builder::new(self.builder);
+        let (fn_ref, fn_argtypes) = self.function_factory.get(fn_name).copied().unwrap();
+        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+        let value = self.value_builder.borrow_mut().get_or_create_global_str(
+            self.builder,
+            value,
+            global_name,
+        );
+        let mut args = [value];
+        let object = LLVMBuildCall2(
+            self.builder,
+            fn_argtypes,
+            fn_ref,
+            args.as_mut_ptr(),
+            args.len() as c_uint,
+            EMPTY_STR.as_ptr(),
+        );
+        let alloca = builder.entry_alloca(object_type, &EMPTY_STR);
+        LLVMBuildStore(self.builder, object, alloca);
+        self.alloc_map.insert(alloc_id, alloca);
+    }
+
+    /// Copies the objects received by a procedure into its frame, as tail calls pass them in a
+    /// buffer that is reused by the next tail call
+    unsafe fn map_procedure_objects(
+        &mut self,
+        objects: LLVMValueRef,
+        len: usize,
+        mut alloc_id: AllocId,
+    ) {
+        let builder = Builder::new(self.builder);
+        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+        for i in 0..len {
+            let object_ptr = builder.gep(objects, object_type, &[i]);
+            let alloca = builder.entry_alloca(object_type, &EMPTY_STR);
+            let object = builder.load(object_type, object_ptr);
+            LLVMBuildStore(self.builder, object, alloca);
+
+            alloc_id += 1;
+            self.alloc_map.insert(alloc_id, alloca);
+        }
+    }
+
     pub fn build_instruction(&mut self, inst: CompilispIr) {
         let builder = Builder::new(self.builder);
 
@@ -72,6 +289,25 @@ impl<'a> CompilispLLVMGenerator<'a> {
                 let alloc = self.build_value(&builder_value);
                 self.alloc_map.insert(alloc_id, alloc);
             }
+            CompilispIr::ConstReal { alloc_id, value } => {
+                let builder_value = Value::VarReal("", value);
+                let alloc = self.build_value(&builder_value);
+                self.alloc_map.insert(alloc_id, alloc);
+            }
+            CompilispIr::ConstBool { alloc_id, value } => {
+                let builder_value = Value::VarBool("", Some(value));
+                let alloc = self.build_value(&builder_value);
+                self.alloc_map.insert(alloc_id, alloc);
+            }
+            CompilispIr::ConstChar { alloc_id, value } => {
+                let builder_value = Value::VarChar("", value);
+                let alloc = self.build_value(&builder_value);
+                self.alloc_map.insert(alloc_id, alloc);
+            }
+            CompilispIr::ConstUnspecified { alloc_id } => {
+                let alloc = self.build_value(&Value::VarUnspecified(""));
+                self.alloc_map.insert(alloc_id, alloc);
+            }
             CompilispIr::GlobalString { alloc_id, value } => {
                 let symbol = VariableString {
                     value: value.as_str(),
@@ -79,10 +315,18 @@ impl<'a> CompilispLLVMGenerator<'a> {
                 let alloc = self.build_value(&symbol);
                 self.alloc_map.insert(alloc_id, alloc);
             }
+            CompilispIr::GlobalSymbol { alloc_id, value } => unsafe {
+                self.build_from_global_str("compilisp_intern_symbol", &value, "symbol", alloc_id);
+            },
+            CompilispIr::GlobalBignum { alloc_id, value } => unsafe {
+                self.build_from_global_str("compilisp_make_bignum", &value, "bignum", alloc_id);
+            },
             CompilispIr::CallProcedure {
                 name,
                 args,
+                env,
                 return_id,
+                tail,
             } => {
                 let call_builder = ProcedureCallBuilder::new(
                     self.function_factory,
@@ -95,32 +339,86 @@ impl<'a> CompilispLLVMGenerator<'a> {
 
                 let return_alloc = self.alloc_map.get(&return_id).unwrap();
                 call_builder
-                    .build_call(name.as_str(), args.as_slice(), *return_alloc)
+                    .build_call(
+                        name.as_str(),
+                        args.as_slice(),
+                        env.as_slice(),
+                        *return_alloc,
+                        tail,
+                    )
                     .unwrap();
             }
-            CompilispIr::IfExpressionEval { cond_alloc } => unsafe {
-                let context = LLVMGetModuleContext(self.module);
-                let cond_value = self.alloc_map.get(&cond_alloc).copied().unwrap();
-                let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
-
-                let value_attr_ptr = builder.gep(cond_value, object_type, &[0, 1]);
+            CompilispIr::CallRuntime {
+                name,
+                args,
+                return_id,
+            } => {
+                let call_builder = ProcedureCallBuilder::new(
+                    self.function_factory,
+                    self.type_factory,
+                    self.builder,
+                    self.module,
+                    &self.alloc_map,
+                    self,
+                );
 
-                let int_type = self.type_factory.get_type(CompilispType::BoolPtr);
-                let casted =
-                    LLVMBuildBitCast(self.builder, value_attr_ptr, int_type, EMPTY_STR.as_ptr());
+                let return_alloc = self.alloc_map.get(&return_id).unwrap();
+                call_builder
+                    .build_runtime_call(name.as_str(), args.as_slice(), *return_alloc)
+                    .unwrap();
+            }
+            CompilispIr::CallClosure {
+                closure_alloc,
+                args,
+                return_id,
+                tail,
+            } => {
+                let call_builder = ProcedureCallBuilder::new(
+                    self.function_factory,
+                    self.type_factory,
+                    self.builder,
+                    self.module,
+                    &self.alloc_map,
+                    self,
+                );
 
-                let cond_value =
-                    builder.load(
-                    LLVMInt1TypeInContext(context),
-                    casted,
+                let closure_value = self.alloc_map.get(&closure_alloc).unwrap();
+                let return_alloc = self.alloc_map.get(&return_id).unwrap();
+                call_builder
+                    .build_closure_call(*closure_value, args.as_slice(), *return_alloc, tail)
+                    .unwrap();
+            }
+            CompilispIr::MakeClosure {
+                alloc_id,
+                function_name,
+                env,
+            } => {
+                let alloc = self.build_value(&Value::VarInt32("", None));
+                let call_builder = ProcedureCallBuilder::new(
+                    self.function_factory,
+                    self.type_factory,
+                    self.builder,
+                    self.module,
+                    &self.alloc_map,
+                    self,
                 );
+                call_builder
+                    .build_closure(function_name.as_str(), env.as_slice(), alloc)
+                    .unwrap();
+                self.alloc_map.insert(alloc_id, alloc);
+            }
+            CompilispIr::IfExpressionEval {
+                cond_alloc,
+                known_boolean,
+            } => unsafe {
+                let context = LLVMGetModuleContext(self.module);
+                let cond_value = self.build_condition(cond_alloc, known_boolean);
 
                 let block_then = LLVMCreateBasicBlockInContext(context, THEN_STR.as_ptr());
                 let block_else = LLVMCreateBasicBlockInContext(context, ELSE_STR.as_ptr());
                 let block_finally = LLVMCreateBasicBlockInContext(context, FINALLY_STR.as_ptr());
                 self.conditional_blocks.push(ConditionalBlock {
                     block_else: Some(block_else),
-                    block_then,
                     block_finally,
                 });
                 builder.cond_br(cond_value, block_then, block_else);
@@ -128,22 +426,22 @@ impl<'a> CompilispLLVMGenerator<'a> {
             },
             CompilispIr::IfExpressionEndThen {
                 result_alloc,
-                cond_alloc,
+                if_alloc,
             }
             | CompilispIr::IfExpressionEndElse {
                 result_alloc,
-                cond_alloc,
+                if_alloc,
             } => unsafe {
                 // Copy block result into conditional result
                 let result_value = self.alloc_map.get(&result_alloc).unwrap();
-                let cond_value = self.alloc_map.get(&cond_alloc).unwrap();
+                let if_value = self.alloc_map.get(&if_alloc).unwrap();
                 let result = LLVMBuildLoad2(
                     self.builder,
                     self.type_factory.get_type(CompilispType::CompilispObject),
                     *result_value,
                     EMPTY_STR.as_ptr(),
                 );
-                LLVMBuildStore(self.builder, result, *cond_value);
+                LLVMBuildStore(self.builder, result, *if_value);
                 let cur_block = self.conditional_blocks.last().unwrap();
                 if cur_block.block_else.is_some() {
                     LLVMBuildBr(self.builder, cur_block.block_finally);
@@ -152,18 +450,126 @@ impl<'a> CompilispLLVMGenerator<'a> {
             CompilispIr::IfExpressionElse => {
                 let cur_block = self.conditional_blocks.last().unwrap();
                 if let Some(block_else) = cur_block.block_else {
-                    unsafe {
-                        builder.insert_and_position_block(block_else)
-                    }
+                    unsafe { builder.insert_and_position_block(block_else) }
                 }
-            },
+            }
             CompilispIr::IfExpressionEndBlock => {
-                let cur_block = self.conditional_blocks.last().unwrap();
-                unsafe {
-                    builder.insert_and_position_block(cur_block.block_finally)
-                }
+                let cur_block = self.conditional_blocks.pop().unwrap();
+                unsafe { builder.insert_and_position_block(cur_block.block_finally) }
             }
-            CompilispIr::IfExpressionEndExpression { .. } => {}
+            CompilispIr::LoopHeader => unsafe {
+                let context = LLVMGetModuleContext(self.module);
+                let block_header = LLVMCreateBasicBlockInContext(context, LOOP_STR.as_ptr());
+                let block_exit = LLVMCreateBasicBlockInContext(context, LOOP_EXIT_STR.as_ptr());
+                self.loop_blocks.push(LoopBlock {
+                    block_header,
+                    block_exit,
+                });
+                builder.br(block_header);
+                builder.insert_and_position_block(block_header);
+            },
+            CompilispIr::LoopBody {
+                cond_alloc,
+                known_boolean,
+            } => unsafe {
+                let context = LLVMGetModuleContext(self.module);
+                let cond_value = self.build_condition(cond_alloc, known_boolean);
+                let block_body = LLVMCreateBasicBlockInContext(context, LOOP_BODY_STR.as_ptr());
+                let cur_loop = self.loop_blocks.last().unwrap();
+                builder.cond_br(cond_value, cur_loop.block_exit, block_body);
+                builder.insert_and_position_block(block_body);
+            },
+            CompilispIr::LoopBackEdge => unsafe {
+                let cur_loop = self.loop_blocks.pop().unwrap();
+                builder.br(cur_loop.block_header);
+                builder.insert_and_position_block(cur_loop.block_exit);
+            },
+            CompilispIr::DeclareVariable { alloc_id } => unsafe {
+                let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+                let alloca = builder.entry_alloca(object_type, &EMPTY_STR);
+                self.alloc_map.insert(alloc_id, alloca);
+            },
+            CompilispIr::DefineVariable {
+                alloc_id,
+                value_alloc,
+            } => unsafe {
+                let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+                let alloca = builder.entry_alloca(object_type, &EMPTY_STR);
+                let value_ptr = self.alloc_map.get(&value_alloc).copied().unwrap();
+                let value = builder.load(object_type, value_ptr);
+                LLVMBuildStore(self.builder, value, alloca);
+                self.alloc_map.insert(alloc_id, alloca);
+            },
+            CompilispIr::SetVariable {
+                alloc_id,
+                value_alloc,
+            } => unsafe {
+                let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+                let variable_ptr = self.alloc_map.get(&alloc_id).copied().unwrap();
+                let value_ptr = self.alloc_map.get(&value_alloc).copied().unwrap();
+                let value = builder.load(object_type, value_ptr);
+                LLVMBuildStore(self.builder, value, variable_ptr);
+            },
+            CompilispIr::MakeBox {
+                alloc_id,
+                value_alloc,
+            } => unsafe {
+                let (fn_ref, fn_argtypes) = self
+                    .function_factory
+                    .get("compilisp_make_box")
+                    .copied()
+                    .unwrap();
+                let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+                let value_ptr = self.alloc_map.get(&value_alloc).copied().unwrap();
+                let mut args = [value_ptr];
+                let box_value = LLVMBuildCall2(
+                    self.builder,
+                    fn_argtypes,
+                    fn_ref,
+                    args.as_mut_ptr(),
+                    args.len() as c_uint,
+                    EMPTY_STR.as_ptr(),
+                );
+                let alloca = builder.entry_alloca(object_type, &EMPTY_STR);
+                LLVMBuildStore(self.builder, box_value, alloca);
+                self.alloc_map.insert(alloc_id, alloca);
+            },
+            CompilispIr::Unbox {
+                alloc_id,
+                box_alloc,
+            } => unsafe {
+                let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+                let boxed_ptr = self.build_boxed_pointer(box_alloc);
+                let alloca = builder.entry_alloca(object_type, &EMPTY_STR);
+                let value = builder.load(object_type, boxed_ptr);
+                LLVMBuildStore(self.builder, value, alloca);
+                self.alloc_map.insert(alloc_id, alloca);
+            },
+            CompilispIr::SetBox {
+                box_alloc,
+                value_alloc,
+            } => unsafe {
+                let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+                let boxed_ptr = self.build_boxed_pointer(box_alloc);
+                let value_ptr = self.alloc_map.get(&value_alloc).copied().unwrap();
+                let value = builder.load(object_type, value_ptr);
+                LLVMBuildStore(self.builder, value, boxed_ptr);
+            },
+            CompilispIr::LoadGlobal { alloc_id, name } => unsafe {
+                let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+                let global = self.get_or_add_global(&name);
+                let alloca = builder.entry_alloca(object_type, &EMPTY_STR);
+                let value = builder.load(object_type, global);
+                LLVMBuildStore(self.builder, value, alloca);
+                self.alloc_map.insert(alloc_id, alloca);
+            },
+            CompilispIr::SetGlobal { name, value_alloc } => unsafe {
+                let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+                let global = self.get_or_add_global(&name);
+                let value_ptr = self.alloc_map.get(&value_alloc).copied().unwrap();
+                let value = builder.load(object_type, value_ptr);
+                LLVMBuildStore(self.builder, value, global);
+            },
             CompilispIr::ProcedureScopeStart => {}
             CompilispIr::ProcedureScopeEnd => {}
             // Same as allocVar
@@ -174,37 +580,46 @@ impl<'a> CompilispLLVMGenerator<'a> {
             }
             CompilispIr::StartProcedure(name) => {
                 let context = unsafe { LLVMGetModuleContext(self.module) };
-                let int_type = self.type_factory.get_type(CompilispType::Int);
-                let obj_type = self.type_factory.get_type(CompilispType::CompilispObject);
-                let obj_ptr_type = self.type_factory.get_pointer(obj_type);
-                let fn_builder = FunctionBuilder::new()
-                    .with_name(name.as_str())
-                    .with_ret_type(obj_type)
-                    .add_arg(int_type)
-                    .add_arg(obj_ptr_type);
-                let new_function = unsafe { fn_builder.build(self.module) };
+                // Procedure may be already declared if it was called before being defined
+                let new_function = self.function_factory.get_or_declare_procedure(
+                    self.module,
+                    self.type_factory,
+                    name.as_str(),
+                );
                 let block = unsafe {
                     LLVMAppendBasicBlockInContext(context, new_function.0, EMPTY_STR.as_ptr())
                 };
                 self.current_function = Some(new_function.0);
                 unsafe { LLVMPositionBuilderAtEnd(self.builder, block) };
             }
-            CompilispIr::MapProcedureArgs(args, mut alloc_id) => {
-                let fun = self.current_function.unwrap();
-                let argc = unsafe { LLVMGetFirstParam(fun) };
-                let argv = unsafe { LLVMGetNextParam(argc) };
-
-                for i in 0..args.len() {
-                    let cur_arg = unsafe {
-                        let object_type =
-                            self.type_factory.get_type(CompilispType::CompilispObject);
-                        builder.gep(argv, object_type, &[i])
-                    };
-
-                    alloc_id += 1;
-                    self.alloc_map.insert(alloc_id, cur_arg);
+            CompilispIr::MapProcedureArgs(args, alloc_id) => unsafe {
+                let argc = LLVMGetParam(self.current_function.unwrap(), 0);
+                let argv = LLVMGetParam(self.current_function.unwrap(), 1);
+                self.build_arity_check(argc, &args);
+                self.map_procedure_objects(argv, args.args.len(), alloc_id);
+                if args.rest.is_some() {
+                    let rest_alloc_id = alloc_id + args.args.len() + 1;
+                    self.build_rest_list(argc, argv, args.args.len(), rest_alloc_id);
                 }
-            }
+            },
+            CompilispIr::MapProcedureEnv(captures, alloc_id) => unsafe {
+                let env = LLVMGetParam(self.current_function.unwrap(), 2);
+                self.map_procedure_objects(env, captures.len(), alloc_id);
+            },
+            CompilispIr::ForwardRuntimeCall { name, return_id } => unsafe {
+                let argc = LLVMGetParam(self.current_function.unwrap(), 0);
+                let argv = LLVMGetParam(self.current_function.unwrap(), 1);
+                let call_builder = ProcedureCallBuilder::new(
+                    self.function_factory,
+                    self.type_factory,
+                    self.builder,
+                    self.module,
+                    &self.alloc_map,
+                    self,
+                );
+                let return_alloc = self.alloc_map.get(&return_id).unwrap();
+                call_builder.build_forwarded_runtime_call(&name, argc, argv, *return_alloc);
+            },
             CompilispIr::EndProcedure(return_id) => {
                 let return_alloc = self.alloc_map.get(&return_id).unwrap();
                 let return_type = self.type_factory.get_type(CompilispType::CompilispObject);
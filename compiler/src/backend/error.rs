@@ -1,6 +1,21 @@
+use std::fmt;
+
 pub type CompilispResult<T> = Result<T, CompilispError>;
 
 #[derive(Debug)]
 pub enum CompilispError {
-    IllFormedSyntax,
+    /// Special form with a wrong number of operands
+    IllFormedSyntax(String),
+    UnboundVariable(String),
+}
+
+impl fmt::Display for CompilispError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompilispError::IllFormedSyntax(keyword) => {
+                write!(f, "Ill-formed special form: {keyword}")
+            }
+            CompilispError::UnboundVariable(name) => write!(f, "Unbound variable: {name}"),
+        }
+    }
 }
@@ -1,7 +1,9 @@
 use crate::backend::function_builder::FunctionBuilder;
 use crate::backend::type_factory::{CompilispType, TypeFactory};
-use llvm_sys::prelude::{LLVMModuleRef, LLVMTypeRef, LLVMValueRef};
+use llvm_sys::core::{LLVMFunctionType, LLVMGetNamedFunction, LLVMGlobalGetValueType};
+use llvm_sys::prelude::{LLVMBool, LLVMModuleRef, LLVMTypeRef, LLVMValueRef};
 use std::collections::HashMap;
+use std::ffi::{c_uint, CString};
 
 // TODO: this shouldn't be clonable
 #[derive(Clone)]
@@ -41,10 +43,115 @@ impl FunctionFactory {
         let cur_fn = unsafe { fn_builder.build(module) };
         function_map.insert("compilisp_procedure_call".to_owned(), cur_fn);
 
+        let fn_builder = FunctionBuilder::new()
+            .with_name("compilisp_make_closure")
+            .with_ret_type(object_type)
+            .add_arg(char_pointer) // function
+            .add_arg(object_pointer) // env
+            .add_arg(int_type); // env size
+        let cur_fn = unsafe { fn_builder.build(module) };
+        function_map.insert("compilisp_make_closure".to_owned(), cur_fn);
+
+        let closure_type = type_factory.get_type(CompilispType::CompilispClosure);
+        let fn_builder = FunctionBuilder::new()
+            .with_name("compilisp_get_closure")
+            .with_ret_type(type_factory.get_pointer(closure_type))
+            .add_arg(object_pointer);
+        let cur_fn = unsafe { fn_builder.build(module) };
+        function_map.insert("compilisp_get_closure".to_owned(), cur_fn);
+
+        let fn_builder = FunctionBuilder::new()
+            .with_name("compilisp_make_list")
+            .with_ret_type(object_type)
+            .add_arg(object_pointer) // values
+            .add_arg(int_type); // values size
+        let cur_fn = unsafe { fn_builder.build(module) };
+        function_map.insert("compilisp_make_list".to_owned(), cur_fn);
+
+        let fn_builder = FunctionBuilder::new()
+            .with_name("compilisp_arity_error")
+            .add_arg(int_type) // args size
+            .add_arg(int_type) // required args
+            .add_arg(int_type); // whether more args are accepted
+        let cur_fn = unsafe { fn_builder.build(module) };
+        function_map.insert("compilisp_arity_error".to_owned(), cur_fn);
+
+        let fn_builder = FunctionBuilder::new()
+            .with_name("compilisp_intern_symbol")
+            .with_ret_type(object_type)
+            .add_arg(char_pointer); // name
+        let cur_fn = unsafe { fn_builder.build(module) };
+        function_map.insert("compilisp_intern_symbol".to_owned(), cur_fn);
+
+        let fn_builder = FunctionBuilder::new()
+            .with_name("compilisp_make_bignum")
+            .with_ret_type(object_type)
+            .add_arg(char_pointer); // digits
+        let cur_fn = unsafe { fn_builder.build(module) };
+        function_map.insert("compilisp_make_bignum".to_owned(), cur_fn);
+
+        let fn_builder = FunctionBuilder::new()
+            .with_name("compilisp_make_box")
+            .with_ret_type(object_type)
+            .add_arg(object_pointer); // value
+        let cur_fn = unsafe { fn_builder.build(module) };
+        function_map.insert("compilisp_make_box".to_owned(), cur_fn);
+
+        let fn_builder = FunctionBuilder::new()
+            .with_name("compilisp_tail_call_buffer")
+            .with_ret_type(object_pointer)
+            .add_arg(int_type); // size
+        let cur_fn = unsafe { fn_builder.build(module) };
+        function_map.insert("compilisp_tail_call_buffer".to_owned(), cur_fn);
+
         Self { function_map }
     }
 
     pub fn get(&self, name: &str) -> Option<&(LLVMValueRef, LLVMTypeRef)> {
         self.function_map.get(name)
     }
+
+    /// Type of compiled procedures, used to call them through a closure
+    pub fn procedure_type(&self, type_factory: &TypeFactory) -> LLVMTypeRef {
+        let int_type = type_factory.get_type(CompilispType::Int);
+        let object_type = type_factory.get_type(CompilispType::CompilispObject);
+        let object_pointer = type_factory.get_pointer(object_type);
+        let mut args = [int_type, object_pointer, object_pointer];
+        unsafe {
+            LLVMFunctionType(
+                object_type,
+                args.as_mut_ptr(),
+                args.len() as c_uint,
+                LLVMBool::from(false),
+            )
+        }
+    }
+
+    /// Gets a compilisp procedure, declaring it if it wasn't built yet
+    /// Procedures receive their arguments as `(argc, argv, env)` and return an object
+    pub fn get_or_declare_procedure(
+        &self,
+        module: LLVMModuleRef,
+        type_factory: &TypeFactory,
+        name: &str,
+    ) -> (LLVMValueRef, LLVMTypeRef) {
+        let int_type = type_factory.get_type(CompilispType::Int);
+        let object_type = type_factory.get_type(CompilispType::CompilispObject);
+        let object_pointer = type_factory.get_pointer(object_type);
+        let c_name = CString::new(name).unwrap();
+        unsafe {
+            let function = LLVMGetNamedFunction(module, c_name.as_ptr());
+            if function.is_null() {
+                FunctionBuilder::new()
+                    .with_name(name)
+                    .with_ret_type(object_type)
+                    .add_arg(int_type)
+                    .add_arg(object_pointer)
+                    .add_arg(object_pointer)
+                    .build(module)
+            } else {
+                (function, LLVMGlobalGetValueType(function))
+            }
+        }
+    }
 }
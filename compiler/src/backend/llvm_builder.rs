@@ -1,7 +1,13 @@
 use crate::backend::runtime::EMPTY_STR;
-use llvm_sys::core::{LLVMBuildCondBr, LLVMBuildInBoundsGEP2, LLVMBuildLoad2, LLVMBuildRet, LLVMConstInt, LLVMInsertExistingBasicBlockAfterInsertBlock, LLVMInt32Type, LLVMPositionBuilderAtEnd};
+use llvm_sys::core::{
+    LLVMBuildAlloca, LLVMBuildBr, LLVMBuildCondBr, LLVMBuildInBoundsGEP2, LLVMBuildLoad2,
+    LLVMBuildRet, LLVMConstInt, LLVMCreateBuilderInContext, LLVMDisposeBuilder,
+    LLVMGetBasicBlockParent, LLVMGetEntryBasicBlock, LLVMGetFirstInstruction, LLVMGetInsertBlock,
+    LLVMGetTypeContext, LLVMInsertExistingBasicBlockAfterInsertBlock, LLVMInt32Type,
+    LLVMPositionBuilderAtEnd, LLVMPositionBuilderBefore,
+};
 use llvm_sys::prelude::{LLVMBasicBlockRef, LLVMBool, LLVMBuilderRef, LLVMTypeRef, LLVMValueRef};
-use std::ffi::{c_uint, c_ulonglong};
+use std::ffi::{c_uint, c_ulonglong, CStr};
 
 pub struct Builder {
     builder: LLVMBuilderRef,
@@ -36,10 +42,36 @@ impl Builder {
         LLVMBuildLoad2(self.builder, type_, value, EMPTY_STR.as_ptr())
     }
 
-    pub unsafe fn cond_br(&self, cond_value: LLVMValueRef, block_then: LLVMBasicBlockRef, block_else: LLVMBasicBlockRef) -> LLVMValueRef {
+    pub unsafe fn cond_br(
+        &self,
+        cond_value: LLVMValueRef,
+        block_then: LLVMBasicBlockRef,
+        block_else: LLVMBasicBlockRef,
+    ) -> LLVMValueRef {
         LLVMBuildCondBr(self.builder, cond_value, block_then, block_else)
     }
 
+    pub unsafe fn br(&self, block: LLVMBasicBlockRef) -> LLVMValueRef {
+        LLVMBuildBr(self.builder, block)
+    }
+
+    /// Allocates stack space in the entry block of the current function, so values built inside
+    /// loops don't grow the stack on each iteration
+    pub unsafe fn entry_alloca(&self, type_: LLVMTypeRef, name: &CStr) -> LLVMValueRef {
+        let current_block = LLVMGetInsertBlock(self.builder);
+        let entry_block = LLVMGetEntryBasicBlock(LLVMGetBasicBlockParent(current_block));
+        let entry_builder = LLVMCreateBuilderInContext(LLVMGetTypeContext(type_));
+        let first_instruction = LLVMGetFirstInstruction(entry_block);
+        if first_instruction.is_null() {
+            LLVMPositionBuilderAtEnd(entry_builder, entry_block);
+        } else {
+            LLVMPositionBuilderBefore(entry_builder, first_instruction);
+        }
+        let alloca = LLVMBuildAlloca(entry_builder, type_, name.as_ptr());
+        LLVMDisposeBuilder(entry_builder);
+        alloca
+    }
+
     pub unsafe fn ret(&self, value: LLVMValueRef) -> LLVMValueRef {
         LLVMBuildRet(self.builder, value)
     }
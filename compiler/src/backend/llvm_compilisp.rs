@@ -1,7 +1,9 @@
-use llvm_sys::prelude::LLVMTypeRef;
+use llvm_sys::prelude::{LLVMTypeRef, LLVMValueRef};
 use llvm_sys::target::LLVMTargetDataRef;
 
 #[allow(unused)]
 extern "C" {
     pub fn LLVMCompilispGetTypeAllocSize(D: LLVMTargetDataRef, Ty: LLVMTypeRef) -> u32;
+    /// Marks a call as `musttail`, not available in the C api
+    pub fn LLVMCompilispSetMustTailCall(Call: LLVMValueRef);
 }
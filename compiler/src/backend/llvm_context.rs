@@ -1,10 +1,12 @@
-use crate::ast::{Expr, ModuleAst};
+use crate::ast::ModuleAst;
 use crate::backend::compilisp_ir::CompilispIrGenerator;
 use crate::backend::debuginfo_builder::DebugInfoBuilder;
+use crate::backend::error::CompilispResult;
 use crate::backend::function_builder::FunctionBuilder;
 use crate::backend::function_factory::FunctionFactory;
 use crate::backend::runtime::RuntimeCompiler;
 use crate::backend::type_factory::TypeFactory;
+use crate::desugar::Desugarer;
 use llvm_sys::core::*;
 use llvm_sys::prelude::*;
 use llvm_sys::target_machine::LLVMGetDefaultTargetTriple;
@@ -21,7 +23,8 @@ impl Context {
         Self { context }
     }
 
-    pub fn add_module(&self, root: ModuleAst) {
+    /// Builds the module into an LLVM IR file next to its source
+    pub fn add_module(&self, root: ModuleAst) -> CompilispResult<()> {
         unsafe {
             let target = LLVMGetDefaultTargetTriple();
             let builder = LLVMCreateBuilderInContext(self.context);
@@ -37,27 +40,30 @@ impl Context {
 
             let mut runtime = RuntimeCompiler::new(function_factory, type_factory);
 
+            let mut desugarer = Desugarer::default();
             let mut ir_generator = CompilispIrGenerator::new();
-            let mut main_buffer = vec![];
-            let mut defines_buffer = vec![];
-            for expr in root.expr_vec {
-                println!("Expr: {expr:?}");
-                ir_generator.process(&expr);
-                match expr {
-                    Expr::DefineProcedure(..) => {
-                        defines_buffer.append(&mut ir_generator.ir_buffer);
-                    }
-                    _ => {
-                        main_buffer.append(&mut ir_generator.ir_buffer);
-                    }
-                }
-                ir_generator.ir_buffer = vec![];
+            let exprs = root
+                .expr_vec
+                .into_iter()
+                .map(|expr| {
+                    println!("Expr: {expr:?}");
+                    desugarer.desugar(expr)
+                })
+                .collect::<Vec<_>>();
+            ir_generator.declare_toplevel(&exprs);
+            for expr in &exprs {
+                ir_generator.process(expr);
             }
-            runtime.process_ir(module, builder, defines_buffer);
+            if let Some(error) = std::mem::take(&mut ir_generator.errors).into_iter().next() {
+                LLVMDisposeBuilder(builder);
+                LLVMDisposeModule(module);
+                return Err(error);
+            }
+            runtime.process_ir(module, builder, ir_generator.procedures_buffer);
 
             LLVMPositionBuilderAtEnd(builder, main_block);
             runtime.init(builder);
-            runtime.process_ir(module, builder, main_buffer);
+            runtime.process_ir(module, builder, ir_generator.ir_buffer);
             runtime.destroy(builder);
 
             //LLVMPositionBuilderAtEnd(builder, main_block);
@@ -77,6 +83,7 @@ impl Context {
             LLVMDisposeBuilder(builder);
             LLVMDisposeModule(module)
         };
+        Ok(())
     }
     unsafe fn build_main_function(&self, module: LLVMModuleRef) -> LLVMBasicBlockRef {
         let char_type = LLVMInt8TypeInContext(self.context);
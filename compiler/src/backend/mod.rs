@@ -1,12 +1,13 @@
 mod compilisp_ir;
 mod compilisp_llvm_generator;
 mod debuginfo_builder;
-mod error;
+pub mod error;
 mod function_builder;
 mod function_factory;
-pub mod llvm_context;
+#[allow(clippy::missing_safety_doc)]
 pub mod llvm_builder;
 mod llvm_compilisp;
+pub mod llvm_context;
 mod procedure_call_builder;
 mod runtime;
 mod type_factory;
@@ -1,11 +1,14 @@
 use crate::backend::compilisp_ir::{Alloc, AllocId, AllocType};
 use crate::backend::compilisp_llvm_generator::{
-    CompilispLLVMGenerator, NUMBER_DISCRIMINATOR, STR_DISCRIMINATOR,
+    CompilispLLVMGenerator, BOOLEAN_DISCRIMINATOR, CHAR_DISCRIMINATOR, NUMBER_DISCRIMINATOR,
+    PROCEDURE_DISCRIMINATOR, REAL_DISCRIMINATOR, STR_DISCRIMINATOR, SYMBOL_DISCRIMINATOR,
+    UNSPECIFIED_DISCRIMINATOR,
 };
 use crate::backend::error::CompilispResult;
 use crate::backend::function_factory::FunctionFactory;
 use crate::backend::llvm_builder::Builder;
-use crate::backend::runtime::EMPTY_STR;
+use crate::backend::llvm_compilisp::LLVMCompilispSetMustTailCall;
+use crate::backend::runtime::{EMPTY_STR, TAIL_CALL_EXIT_STR};
 use crate::backend::type_factory::{CompilispType, TypeFactory};
 use crate::backend::value_builder::Value;
 use llvm_sys::core::*;
@@ -45,133 +48,297 @@ impl<'a> ProcedureCallBuilder<'a> {
         &self,
         name: &str,
         args: &[Alloc],
+        env: &[Alloc],
         return_alloc: LLVMValueRef,
+        tail: bool,
     ) -> CompilispResult<LLVMValueRef> {
-        match name {
-            "begin" | "+" | "display" | "<" => unsafe {
-                Ok(self.procedure_runtime_call(name, args, return_alloc))
-            },
-            _ => unsafe { self.procedure_function_call(name, args, return_alloc) },
-        }
+        unsafe { self.procedure_function_call(name, args, env, return_alloc, tail) }
+    }
+
+    /// Calls a procedure implemented by the runtime, dispatched by its name
+    pub fn build_runtime_call(
+        &self,
+        name: &str,
+        args: &[Alloc],
+        return_alloc: LLVMValueRef,
+    ) -> CompilispResult<LLVMValueRef> {
+        unsafe { Ok(self.procedure_runtime_call(name, args, return_alloc)) }
     }
 
     unsafe fn procedure_function_call(
         &self,
         name: &str,
         args: &[Alloc],
+        env: &[Alloc],
         result_alloc: LLVMValueRef,
+        tail: bool,
     ) -> CompilispResult<LLVMValueRef> {
-        let c_name = CString::new(name).unwrap();
-        let function = LLVMGetNamedFunction(self.module, c_name.as_ptr());
-        let argc_type = self.type_factory.get_type(CompilispType::Int);
-        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
-        let obj_arr_type = self.type_factory.get_pointer(object_type);
-        let mut args_types = [argc_type, obj_arr_type];
-        let args_size = args_types.len() as c_uint;
-        let args_ptr = args_types.as_mut_ptr();
-        let fn_type = LLVMFunctionType(object_type, args_ptr, args_size, LLVMBool::from(false));
+        let (function, fn_type) =
+            self.function_factory
+                .get_or_declare_procedure(self.module, self.type_factory, name);
+        let (args_array, env_array) = if tail {
+            self.build_tail_call_arrays(args, env)
+        } else {
+            (self.build_object_array(args), self.build_object_array(env))
+        };
+        Ok(self.build_procedure_call(
+            function,
+            fn_type,
+            args_array,
+            args.len(),
+            env_array,
+            result_alloc,
+            tail,
+        ))
+    }
 
-        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
-        let object_array_type = LLVMArrayType(object_type, args.len() as _);
-        let object_array =
-            unsafe { LLVMBuildAlloca(self.builder, object_array_type, EMPTY_STR.as_ptr()) };
+    /// Calls the procedure stored in a closure object, passing its captured environment
+    pub fn build_closure_call(
+        &self,
+        closure_alloc: LLVMValueRef,
+        args: &[Alloc],
+        return_alloc: LLVMValueRef,
+        tail: bool,
+    ) -> CompilispResult<LLVMValueRef> {
+        unsafe {
+            let builder = Builder::new(self.builder);
+            let (fn_ref, fn_argtypes) = self
+                .function_factory
+                .get("compilisp_get_closure")
+                .copied()
+                .unwrap();
+            let mut get_args = [closure_alloc];
+            let closure = LLVMBuildCall2(
+                self.builder,
+                fn_argtypes,
+                fn_ref,
+                get_args.as_mut_ptr(),
+                get_args.len() as c_uint,
+                EMPTY_STR.as_ptr(),
+            );
+            let closure_type = self.type_factory.get_type(CompilispType::CompilispClosure);
+            let char_pointer = self.type_factory.get_type(CompilispType::CharPtr);
+            let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+            let object_pointer = self.type_factory.get_pointer(object_type);
 
-        let builder = Builder::new(self.builder);
-        for (i, arg) in args.iter().enumerate() {
-            let val_indexes = [0, i];
-            let obj_type_indexes = [0, 0];
-
-            let object_idx = builder.gep(object_array, object_array_type, &val_indexes);
-            let type_attr_ptr = builder.gep(object_idx, object_type, &obj_type_indexes);
-
-            let discriminator = match arg.alloc_type {
-                AllocType::Int => Value::ConstInt(NUMBER_DISCRIMINATOR),
-                AllocType::String => Value::ConstInt(STR_DISCRIMINATOR),
-                AllocType::Bool => {
-                    todo!()
-                }
+            let function_ptr = builder.gep(closure, closure_type, &[0, 0]);
+            let function = builder.load(char_pointer, function_ptr);
+            let env_ptr = builder.gep(closure, closure_type, &[0, 1]);
+            let env = builder.load(object_pointer, env_ptr);
+
+            let fn_type = self.function_factory.procedure_type(self.type_factory);
+            let function = LLVMBuildBitCast(
+                self.builder,
+                function,
+                self.type_factory.get_pointer(fn_type),
+                EMPTY_STR.as_ptr(),
+            );
+            // Closure environments live in the heap, so they can be passed to tail calls as is
+            let args_array = if tail {
+                self.build_tail_call_arrays(args, &[]).0
+            } else {
+                self.build_object_array(args)
             };
-            let value_discriminator = self.expr_builder.build_value(&discriminator);
+            Ok(self.build_procedure_call(
+                function,
+                fn_type,
+                args_array,
+                args.len(),
+                env,
+                return_alloc,
+                tail,
+            ))
+        }
+    }
 
-            LLVMBuildStore(self.builder, value_discriminator, type_attr_ptr);
-            // Copy Compilisp object value
-            let value_ptr = *self.alloc_map.get(&arg.id).unwrap();
-            let src_value_type = self.type_factory.get_type(CompilispType::CompilispObject);
-            let src_value =
-                LLVMBuildLoad2(self.builder, src_value_type, value_ptr, EMPTY_STR.as_ptr());
-            LLVMBuildStore(self.builder, src_value, object_idx);
+    /// Creates a closure object from a compiled procedure and the values it captures
+    pub fn build_closure(
+        &self,
+        function_name: &str,
+        env: &[Alloc],
+        closure_alloc: LLVMValueRef,
+    ) -> CompilispResult<LLVMValueRef> {
+        unsafe {
+            let (function, _) = self.function_factory.get_or_declare_procedure(
+                self.module,
+                self.type_factory,
+                function_name,
+            );
+            let (fn_ref, fn_argtypes) = self
+                .function_factory
+                .get("compilisp_make_closure")
+                .copied()
+                .unwrap();
+            let char_pointer = self.type_factory.get_type(CompilispType::CharPtr);
+            let function_ptr =
+                LLVMBuildBitCast(self.builder, function, char_pointer, EMPTY_STR.as_ptr());
+            let env_array = self.build_object_array(env);
+            let env_size_value = self
+                .expr_builder
+                .build_value(&Value::ConstInt(env.len() as i32));
+            let mut args = [function_ptr, env_array, env_size_value];
+            let result_value = LLVMBuildCall2(
+                self.builder,
+                fn_argtypes,
+                fn_ref,
+                args.as_mut_ptr(),
+                args.len() as c_uint,
+                EMPTY_STR.as_ptr(),
+            );
+            LLVMBuildStore(self.builder, result_value, closure_alloc);
+            Ok(closure_alloc)
         }
-        let object_array_ptr = builder.gep(object_array, object_array_type, &[0, 0]);
+    }
 
+    /// Tail calls return the callee result right away, releasing the caller frame
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn build_procedure_call(
+        &self,
+        function: LLVMValueRef,
+        fn_type: LLVMTypeRef,
+        args_array: LLVMValueRef,
+        args_len: usize,
+        env_array: LLVMValueRef,
+        result_alloc: LLVMValueRef,
+        tail: bool,
+    ) -> LLVMValueRef {
         let stack_size_value = self
             .expr_builder
-            .build_value(&Value::ConstInt(args.len() as i32));
+            .build_value(&Value::ConstInt(args_len as i32));
 
-        let mut args = [stack_size_value, object_array_ptr];
+        let mut args = [stack_size_value, args_array, env_array];
         let result_value = LLVMBuildCall2(
             self.builder,
             fn_type,
             function,
             args.as_mut_ptr(),
-            args_size,
+            args.len() as c_uint,
             EMPTY_STR.as_ptr(),
         );
-        LLVMBuildStore(self.builder, result_value, result_alloc);
-        Ok(result_alloc)
+        if tail {
+            LLVMCompilispSetMustTailCall(result_value);
+            let builder = Builder::new(self.builder);
+            builder.ret(result_value);
+            // Code following a tail call is unreachable, but it's still built into its own block
+            let context = LLVMGetModuleContext(self.module);
+            let block = LLVMCreateBasicBlockInContext(context, TAIL_CALL_EXIT_STR.as_ptr());
+            builder.insert_and_position_block(block);
+        } else {
+            LLVMBuildStore(self.builder, result_value, result_alloc);
+        }
+        result_alloc
     }
 
-    unsafe fn procedure_runtime_call(
+    /// Copies the objects into a stack array, returns a pointer to its first element
+    unsafe fn build_object_array(&self, values: &[Alloc]) -> LLVMValueRef {
+        let builder = Builder::new(self.builder);
+        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
+        let object_array_type = LLVMArrayType(object_type, values.len() as _);
+        let object_array = builder.entry_alloca(object_array_type, &EMPTY_STR);
+        let object_array_ptr = builder.gep(object_array, object_array_type, &[0, 0]);
+        self.copy_objects(object_array_ptr, values);
+        object_array_ptr
+    }
+
+    /// The caller frame is released on tail calls, so arguments and environment are copied into
+    /// the runtime tail call buffer instead of the stack
+    unsafe fn build_tail_call_arrays(
         &self,
-        opname: &str,
         args: &[Alloc],
-        result_alloc: LLVMValueRef,
-    ) -> LLVMValueRef {
+        env: &[Alloc],
+    ) -> (LLVMValueRef, LLVMValueRef) {
         let builder = Builder::new(self.builder);
-
         let (fn_ref, fn_argtypes) = self
             .function_factory
-            .get("compilisp_procedure_call")
+            .get("compilisp_tail_call_buffer")
             .copied()
             .unwrap();
-        let stack_size_value = self
+        let buffer_size_value = self
             .expr_builder
-            .build_value(&Value::ConstInt(args.len() as i32));
-
+            .build_value(&Value::ConstInt((args.len() + env.len()) as i32));
+        let mut buffer_args = [buffer_size_value];
+        let args_array = LLVMBuildCall2(
+            self.builder,
+            fn_argtypes,
+            fn_ref,
+            buffer_args.as_mut_ptr(),
+            buffer_args.len() as c_uint,
+            EMPTY_STR.as_ptr(),
+        );
         let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
-        let object_array_type = LLVMArrayType(object_type, args.len() as _);
-        let object_array =
-            unsafe { LLVMBuildAlloca(self.builder, object_array_type, EMPTY_STR.as_ptr()) };
-
-        let name = "__operation_".to_string() + opname;
-        let opname = self.expr_builder.build_value(&Value::GlobalString {
-            value: opname,
-            name: name.as_str(),
-        });
+        let env_array = builder.gep(args_array, object_type, &[args.len()]);
+        self.copy_objects(args_array, args);
+        self.copy_objects(env_array, env);
+        (args_array, env_array)
+    }
 
-        for (i, arg) in args.iter().enumerate() {
-            let object_idx = builder.gep(object_array, object_array_type, &[0, i]);
+    unsafe fn copy_objects(&self, target: LLVMValueRef, values: &[Alloc]) {
+        let builder = Builder::new(self.builder);
+        let object_type = self.type_factory.get_type(CompilispType::CompilispObject);
 
+        for (i, value) in values.iter().enumerate() {
+            let object_idx = builder.gep(target, object_type, &[i]);
             let type_attr_ptr = builder.gep(object_idx, object_type, &[0, 0]);
 
-            let discriminator = match arg.alloc_type {
-                AllocType::Int => Value::ConstInt(NUMBER_DISCRIMINATOR),
-                AllocType::String => Value::ConstInt(STR_DISCRIMINATOR),
-                AllocType::Bool => {
-                    todo!()
-                }
+            let discriminator = match value.alloc_type {
+                AllocType::Int => Some(NUMBER_DISCRIMINATOR),
+                AllocType::Real => Some(REAL_DISCRIMINATOR),
+                AllocType::String => Some(STR_DISCRIMINATOR),
+                AllocType::Symbol => Some(SYMBOL_DISCRIMINATOR),
+                AllocType::Procedure => Some(PROCEDURE_DISCRIMINATOR),
+                AllocType::Bool => Some(BOOLEAN_DISCRIMINATOR),
+                AllocType::Char => Some(CHAR_DISCRIMINATOR),
+                AllocType::Unspecified => Some(UNSPECIFIED_DISCRIMINATOR),
+                // Objects of unknown type already hold their discriminator
+                AllocType::Object => None,
             };
-            let value_discriminator = self.expr_builder.build_value(&discriminator);
-
-            LLVMBuildStore(self.builder, value_discriminator, type_attr_ptr);
+            if let Some(discriminator) = discriminator {
+                let value_discriminator = self
+                    .expr_builder
+                    .build_value(&Value::ConstInt(discriminator));
+                LLVMBuildStore(self.builder, value_discriminator, type_attr_ptr);
+            }
             // Copy Compilisp object value
-            let value_ptr = *self.alloc_map.get(&arg.id).unwrap();
-            let src_value_type = self.type_factory.get_type(CompilispType::CompilispObject);
+            let value_ptr = *self.alloc_map.get(&value.id).unwrap();
             let src_value =
-                LLVMBuildLoad2(self.builder, src_value_type, value_ptr, EMPTY_STR.as_ptr());
+                LLVMBuildLoad2(self.builder, object_type, value_ptr, EMPTY_STR.as_ptr());
             LLVMBuildStore(self.builder, src_value, object_idx);
         }
+    }
 
-        let object_array_ptr = builder.gep(object_array, object_array_type, &[0, 0]);
+    unsafe fn procedure_runtime_call(
+        &self,
+        opname: &str,
+        args: &[Alloc],
+        result_alloc: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let stack_size_value = self
+            .expr_builder
+            .build_value(&Value::ConstInt(args.len() as i32));
+        let object_array_ptr = self.build_object_array(args);
+        self.build_forwarded_runtime_call(opname, stack_size_value, object_array_ptr, result_alloc)
+    }
+
+    /// Calls a runtime procedure with an array of objects already built, like the arguments of
+    /// the current procedure
+    pub unsafe fn build_forwarded_runtime_call(
+        &self,
+        opname: &str,
+        stack_size_value: LLVMValueRef,
+        object_array_ptr: LLVMValueRef,
+        result_alloc: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let (fn_ref, fn_argtypes) = self
+            .function_factory
+            .get("compilisp_procedure_call")
+            .copied()
+            .unwrap();
+
+        let name = "__operation_".to_string() + opname;
+        let opname = self.expr_builder.build_value(&Value::GlobalString {
+            value: opname,
+            name: name.as_str(),
+        });
 
         let mut args = [opname, object_array_ptr, stack_size_value];
         let res_name = CString::new("result").unwrap();
@@ -20,6 +20,12 @@ lazy_static! {
     pub static ref THEN_STR: CString = CString::new("then").unwrap();
     pub static ref ELSE_STR: CString = CString::new("else").unwrap();
     pub static ref FINALLY_STR: CString = CString::new("finally").unwrap();
+    pub static ref LOOP_STR: CString = CString::new("loop").unwrap();
+    pub static ref LOOP_BODY_STR: CString = CString::new("loop_body").unwrap();
+    pub static ref LOOP_EXIT_STR: CString = CString::new("loop_exit").unwrap();
+    pub static ref TAIL_CALL_EXIT_STR: CString = CString::new("tail_call_exit").unwrap();
+    pub static ref ARITY_ERROR_STR: CString = CString::new("arity_error").unwrap();
+    pub static ref ARGS_STR: CString = CString::new("args").unwrap();
 }
 
 impl RuntimeCompiler {
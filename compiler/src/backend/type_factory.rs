@@ -1,6 +1,6 @@
 use llvm_sys::core::{
-    LLVMGetModuleContext, LLVMInt1TypeInContext, LLVMInt32TypeInContext, LLVMInt8TypeInContext,
-    LLVMPointerType, LLVMStructCreateNamed, LLVMStructSetBody,
+    LLVMDoubleTypeInContext, LLVMGetModuleContext, LLVMInt1TypeInContext, LLVMInt32TypeInContext,
+    LLVMInt8TypeInContext, LLVMPointerType, LLVMStructCreateNamed, LLVMStructSetBody,
 };
 use llvm_sys::prelude::{LLVMBool, LLVMModuleRef, LLVMTypeRef};
 use std::collections::HashMap;
@@ -12,8 +12,10 @@ pub enum CompilispType {
     CharPtr,
     Int,
     IntPtr,
+    DoublePtr,
     BoolPtr,
     CompilispObject,
+    CompilispClosure,
 }
 
 pub struct TypeFactory {
@@ -37,13 +39,17 @@ impl TypeFactory {
             let int_pointer = LLVMPointerType(int_type, 0);
             type_map.insert(CompilispType::IntPtr, int_pointer);
 
+            let double_type = LLVMDoubleTypeInContext(context);
+            let double_pointer = LLVMPointerType(double_type, 0);
+            type_map.insert(CompilispType::DoublePtr, double_pointer);
+
             let bool_type = LLVMInt1TypeInContext(context);
             let bool_pointer = LLVMPointerType(bool_type, 0);
             type_map.insert(CompilispType::BoolPtr, bool_pointer);
 
             let struct_name = CString::new("compilisp_object").unwrap();
             let compilisp_object = LLVMStructCreateNamed(context, struct_name.as_ptr());
-            // Biggest object data type is a pointer
+            // Biggest object data types are pointers and doubles
             // Int objects are either smaller or equal pointers
             let mut members = [int_type, char_pointer];
             LLVMStructSetBody(
@@ -53,6 +59,18 @@ impl TypeFactory {
                 LLVMBool::from(false),
             );
             type_map.insert(CompilispType::CompilispObject, compilisp_object);
+
+            // Procedure, captured environment and its size
+            let struct_name = CString::new("compilisp_closure").unwrap();
+            let compilisp_closure = LLVMStructCreateNamed(context, struct_name.as_ptr());
+            let mut members = [char_pointer, LLVMPointerType(compilisp_object, 0), int_type];
+            LLVMStructSetBody(
+                compilisp_closure,
+                members.as_mut_ptr(),
+                3,
+                LLVMBool::from(false),
+            );
+            type_map.insert(CompilispType::CompilispClosure, compilisp_closure);
         }
         Self { type_map }
     }
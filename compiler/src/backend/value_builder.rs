@@ -1,9 +1,13 @@
+use crate::backend::compilisp_llvm_generator::{
+    BOOLEAN_DISCRIMINATOR, CHAR_DISCRIMINATOR, NUMBER_DISCRIMINATOR, REAL_DISCRIMINATOR,
+    STR_DISCRIMINATOR, UNSPECIFIED_DISCRIMINATOR,
+};
 use crate::backend::llvm_builder::Builder;
 use crate::backend::runtime::EMPTY_STR;
 use crate::backend::type_factory::{CompilispType, TypeFactory};
 use llvm_sys::core::{
-    LLVMBuildAlloca, LLVMBuildBitCast, LLVMBuildGlobalStringPtr, LLVMBuildStore, LLVMConstInt,
-    LLVMInt1TypeInContext,
+    LLVMBuildBitCast, LLVMBuildGlobalStringPtr, LLVMBuildStore, LLVMConstInt, LLVMConstReal,
+    LLVMDoubleTypeInContext, LLVMInt1TypeInContext,
 };
 use llvm_sys::prelude::{LLVMBool, LLVMBuilderRef, LLVMContextRef, LLVMValueRef};
 use std::collections::HashMap;
@@ -20,6 +24,9 @@ pub enum Value<'a> {
     ConstInt(i32),
     VarInt32(&'a str, Option<i32>),
     VarBool(&'a str, Option<bool>),
+    VarUnspecified(&'a str),
+    VarChar(&'a str, char),
+    VarReal(&'a str, f64),
 }
 
 impl ValueBuilder {
@@ -36,21 +43,18 @@ impl ValueBuilder {
         let g_builder = Builder::new(builder);
         match value {
             Value::GlobalString { name, value } => {
-                let escaped_value = value.replace("\\n", "\n");
-                self.get_or_create_global_str(builder, &escaped_value, name)
+                self.get_or_create_global_str(builder, value, name)
             }
             Value::VariableString { value } => {
-                let escaped_value = value.replace("\\n", "\n");
-
                 let alloca_type = type_factory.get_type(CompilispType::CompilispObject);
-                let alloca = unsafe { LLVMBuildAlloca(builder, alloca_type, EMPTY_STR.as_ptr()) };
+                let alloca = unsafe { g_builder.entry_alloca(alloca_type, &EMPTY_STR) };
 
                 let type_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 0]);
 
-                let const_disc_value = self.build_const_int(2, type_factory);
+                let const_disc_value = self.build_const_int(STR_DISCRIMINATOR, type_factory);
                 unsafe { LLVMBuildStore(builder, const_disc_value, type_attr_ptr) };
 
-                let global_str = self.get_or_create_global_str(builder, &escaped_value, "name");
+                let global_str = self.get_or_create_global_str(builder, value, "name");
                 let value_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 1]);
 
                 // Save constant in stack
@@ -61,11 +65,11 @@ impl ValueBuilder {
             Value::VarInt32(name, init_value) => {
                 let name = CString::new(*name).unwrap();
                 let alloca_type = type_factory.get_type(CompilispType::CompilispObject);
-                let alloca = unsafe { LLVMBuildAlloca(builder, alloca_type, name.as_ptr()) };
+                let alloca = unsafe { g_builder.entry_alloca(alloca_type, &name) };
                 if let Some(value) = *init_value {
                     // Create constant `num`
                     let type_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 0]);
-                    let const_disc_value = self.build_const_int(0, type_factory);
+                    let const_disc_value = self.build_const_int(NUMBER_DISCRIMINATOR, type_factory);
                     unsafe { LLVMBuildStore(builder, const_disc_value, type_attr_ptr) };
                     let value_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 1]);
                     let int_type = type_factory.get_type(CompilispType::IntPtr);
@@ -79,17 +83,71 @@ impl ValueBuilder {
             }
             Value::VarBool(name, init_value) => {
                 let name = CString::new(*name).unwrap();
-                let alloca_type = unsafe { LLVMInt1TypeInContext(context) };
-                let alloca = unsafe { LLVMBuildAlloca(builder, alloca_type, name.as_ptr()) };
+                let alloca_type = type_factory.get_type(CompilispType::CompilispObject);
+                let alloca = unsafe { g_builder.entry_alloca(alloca_type, &name) };
                 if let Some(value) = *init_value {
+                    let type_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 0]);
+                    let const_disc_value =
+                        self.build_const_int(BOOLEAN_DISCRIMINATOR, type_factory);
+                    unsafe { LLVMBuildStore(builder, const_disc_value, type_attr_ptr) };
+                    let value_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 1]);
+                    let bool_type = type_factory.get_type(CompilispType::BoolPtr);
+                    let casted =
+                        LLVMBuildBitCast(builder, value_attr_ptr, bool_type, EMPTY_STR.as_ptr());
+                    // Save constant in stack
                     let const_value = unsafe {
-                        LLVMConstInt(alloca_type, value as c_ulonglong, LLVMBool::from(false))
+                        LLVMConstInt(
+                            LLVMInt1TypeInContext(context),
+                            value as c_ulonglong,
+                            LLVMBool::from(false),
+                        )
                     };
-                    // Save constant in stack
-                    unsafe { LLVMBuildStore(builder, const_value, alloca) };
+                    unsafe { LLVMBuildStore(builder, const_value, casted) };
                 }
                 alloca
             }
+            Value::VarChar(name, value) => {
+                let name = CString::new(*name).unwrap();
+                let alloca_type = type_factory.get_type(CompilispType::CompilispObject);
+                let alloca = unsafe { g_builder.entry_alloca(alloca_type, &name) };
+                let type_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 0]);
+                let const_disc_value = self.build_const_int(CHAR_DISCRIMINATOR, type_factory);
+                unsafe { LLVMBuildStore(builder, const_disc_value, type_attr_ptr) };
+                let value_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 1]);
+                let int_type = type_factory.get_type(CompilispType::IntPtr);
+                let casted =
+                    LLVMBuildBitCast(builder, value_attr_ptr, int_type, EMPTY_STR.as_ptr());
+                // Characters are stored as their code point
+                let const_value = self.build_const_int(*value as i32, type_factory);
+                unsafe { LLVMBuildStore(builder, const_value, casted) };
+                alloca
+            }
+            Value::VarReal(name, value) => {
+                let name = CString::new(*name).unwrap();
+                let alloca_type = type_factory.get_type(CompilispType::CompilispObject);
+                let alloca = unsafe { g_builder.entry_alloca(alloca_type, &name) };
+                let type_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 0]);
+                let const_disc_value = self.build_const_int(REAL_DISCRIMINATOR, type_factory);
+                unsafe { LLVMBuildStore(builder, const_disc_value, type_attr_ptr) };
+                let value_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 1]);
+                let double_type = type_factory.get_type(CompilispType::DoublePtr);
+                let casted =
+                    LLVMBuildBitCast(builder, value_attr_ptr, double_type, EMPTY_STR.as_ptr());
+                let const_value =
+                    unsafe { LLVMConstReal(LLVMDoubleTypeInContext(context), *value) };
+                unsafe { LLVMBuildStore(builder, const_value, casted) };
+                alloca
+            }
+            Value::VarUnspecified(name) => {
+                let name = CString::new(*name).unwrap();
+                let alloca_type = type_factory.get_type(CompilispType::CompilispObject);
+                let alloca = unsafe { g_builder.entry_alloca(alloca_type, &name) };
+                let type_attr_ptr = g_builder.gep(alloca, alloca_type, &[0, 0]);
+                let const_disc_value =
+                    self.build_const_int(UNSPECIFIED_DISCRIMINATOR, type_factory);
+                unsafe { LLVMBuildStore(builder, const_disc_value, type_attr_ptr) };
+                alloca
+            }
         }
     }
 
@@ -0,0 +1,218 @@
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommentKind {
+    /// `;` up to the end of the line
+    Line,
+    /// `#| ... |#`, block comments can be nested
+    Block,
+    /// `#;` and the datum following it
+    Datum,
+}
+
+/// Comment found in the source, the parser ignores them but tools like formatters keep them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub kind: CommentKind,
+    /// Bytes of the source taken by the comment, including its delimiters
+    pub span: Range<usize>,
+}
+
+impl Comment {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.span.clone()]
+    }
+}
+
+/// Finds the comments in the source, skipping strings and character literals
+pub fn comments(source: &str) -> Vec<Comment> {
+    let scanner = Scanner {
+        source: source.as_bytes(),
+    };
+    let mut comments = Vec::new();
+    let mut pos = 0;
+    while pos < source.len() {
+        if let Some((kind, end)) = scanner.comment(pos) {
+            comments.push(Comment {
+                kind,
+                span: pos..end,
+            });
+            pos = end;
+        } else if scanner.at(pos, "\"") {
+            pos = scanner.string_end(pos);
+        } else if scanner.at(pos, "#\\") {
+            pos = scanner.char_end(pos);
+        } else {
+            pos += 1;
+        }
+    }
+    comments
+}
+
+/// Replaces the comments with whitespace, so the parser reports the same locations
+pub fn strip_comments(source: &str) -> String {
+    let mut stripped = source.as_bytes().to_vec();
+    for comment in comments(source) {
+        for byte in &mut stripped[comment.span] {
+            if *byte != b'\n' {
+                *byte = b' ';
+            }
+        }
+    }
+    // Comments start and end at ascii characters, so whole characters are replaced
+    String::from_utf8(stripped).expect("Comments take whole characters")
+}
+
+struct Scanner<'a> {
+    source: &'a [u8],
+}
+
+impl Scanner<'_> {
+    fn at(&self, pos: usize, prefix: &str) -> bool {
+        self.source[pos..].starts_with(prefix.as_bytes())
+    }
+
+    /// Kind of the comment starting at `pos` and where it ends
+    fn comment(&self, pos: usize) -> Option<(CommentKind, usize)> {
+        if self.at(pos, ";") {
+            let end = self.source[pos..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(self.source.len(), |len| pos + len);
+            Some((CommentKind::Line, end))
+        } else if self.at(pos, "#|") {
+            Some((CommentKind::Block, self.block_end(pos)))
+        } else if self.at(pos, "#;") {
+            Some((CommentKind::Datum, self.datum_end(pos + 2)))
+        } else {
+            None
+        }
+    }
+
+    fn block_end(&self, mut pos: usize) -> usize {
+        let mut depth = 0;
+        while pos < self.source.len() {
+            if self.at(pos, "#|") {
+                depth += 1;
+                pos += 2;
+            } else if self.at(pos, "|#") {
+                depth -= 1;
+                pos += 2;
+                if depth == 0 {
+                    return pos;
+                }
+            } else {
+                pos += 1;
+            }
+        }
+        self.source.len()
+    }
+
+    fn string_end(&self, mut pos: usize) -> usize {
+        pos += 1;
+        while pos < self.source.len() {
+            match self.source[pos] {
+                b'\\' => pos += 2,
+                b'"' => return pos + 1,
+                _ => pos += 1,
+            }
+        }
+        self.source.len()
+    }
+
+    /// Skips `#\` and the character after it, which can be a delimiter like `#\;`
+    fn char_end(&self, mut pos: usize) -> usize {
+        pos += 3;
+        while pos < self.source.len() && self.source[pos] & 0xC0 == 0x80 {
+            pos += 1;
+        }
+        pos.min(self.source.len())
+    }
+
+    /// Skips whitespace and comments
+    fn atmosphere_end(&self, mut pos: usize) -> usize {
+        loop {
+            while pos < self.source.len() && self.source[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            match self.comment(pos) {
+                Some((_, end)) => pos = end,
+                None => return pos,
+            }
+        }
+    }
+
+    fn datum_end(&self, pos: usize) -> usize {
+        let mut pos = self.atmosphere_end(pos);
+        if pos >= self.source.len() {
+            return pos;
+        }
+        let open = ["(", "[", "#("].iter().find(|open| self.at(pos, open));
+        if let Some(open) = open {
+            pos += open.len();
+            loop {
+                pos = self.atmosphere_end(pos);
+                if pos >= self.source.len() {
+                    return pos;
+                }
+                if matches!(self.source[pos], b')' | b']') {
+                    return pos + 1;
+                }
+                pos = self.datum_end(pos);
+            }
+        }
+        if self.at(pos, "\"") {
+            return self.string_end(pos);
+        }
+        if self.at(pos, ",@") {
+            return self.datum_end(pos + 2);
+        }
+        if matches!(self.source[pos], b'\'' | b'`' | b',') {
+            return self.datum_end(pos + 1);
+        }
+        if self.at(pos, "#\\") {
+            pos = self.char_end(pos);
+        }
+        while pos < self.source.len() && !is_delimiter(self.source[pos]) {
+            pos += 1;
+        }
+        pos
+    }
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    byte.is_ascii_whitespace() || matches!(byte, b'(' | b')' | b'[' | b']' | b'"' | b';')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_comments() {
+        let source = "(f 1) ; line\n#| outer #| inner |# |# (g #;(h \"(\") 2)";
+        let found = comments(source)
+            .iter()
+            .map(|comment| (comment.kind, comment.text(source)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (CommentKind::Line, "; line"),
+                (CommentKind::Block, "#| outer #| inner |# |#"),
+                (CommentKind::Datum, "#;(h \"(\")"),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_strings_and_chars() {
+        assert!(comments(r#"(display "; not a comment") #\; #\""#).is_empty());
+    }
+
+    #[test]
+    fn strip_keeps_locations() {
+        let source = "(a #;b c) ; d\n(e)";
+        assert_eq!(strip_comments(source), "(a     c)    \n(e)");
+    }
+}
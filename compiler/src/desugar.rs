@@ -0,0 +1,426 @@
+use crate::ast::{sequence, CondClause, Expr};
+
+/// Rewrites derived expressions into the core forms processed by the ir generator
+#[derive(Default)]
+pub struct Desugarer {
+    temp_id: usize,
+}
+
+impl Desugarer {
+    pub fn desugar(&mut self, expr: Expr) -> Expr {
+        match expr {
+            Expr::Cond(clauses, else_body) => self.desugar_cond(clauses, else_body),
+            Expr::Case(key, clauses, else_body) => self.desugar_case(*key, clauses, else_body),
+            Expr::And(exprs) => self.desugar_and(exprs),
+            Expr::Or(exprs) => self.desugar_or(exprs),
+            Expr::When(test, body) => {
+                let test = self.desugar(*test);
+                let body = self.desugar_body(body);
+                if_expr(test, body, None)
+            }
+            // The result is unspecified when the test is true, so the test value is returned
+            Expr::Unless(test, body) => {
+                let body = sequence(body);
+                self.desugar_or(vec![*test, body])
+            }
+            Expr::List(items) => Expr::List(self.desugar_all(items)),
+            Expr::Procedure(name, args) => Expr::Procedure(name, self.desugar_all(args)),
+            Expr::LetProcedure(symbols, body) => {
+                Expr::LetProcedure(self.desugar_bindings(symbols), self.desugar_boxed(body))
+            }
+            Expr::NamedLetProcedure(name, symbols, body) => Expr::NamedLetProcedure(
+                name,
+                self.desugar_bindings(symbols),
+                self.desugar_boxed(body),
+            ),
+            Expr::LetStarProcedure(symbols, body) => {
+                Expr::LetStarProcedure(self.desugar_bindings(symbols), self.desugar_boxed(body))
+            }
+            Expr::LetrecProcedure(symbols, body) => {
+                Expr::LetrecProcedure(self.desugar_bindings(symbols), self.desugar_boxed(body))
+            }
+            Expr::LetrecStarProcedure(symbols, body) => {
+                Expr::LetrecStarProcedure(self.desugar_bindings(symbols), self.desugar_boxed(body))
+            }
+            Expr::Lambda(args, body) => Expr::Lambda(args, self.desugar_boxed(body)),
+            Expr::DefineExpr(name, value) => Expr::DefineExpr(name, self.desugar_boxed(value)),
+            Expr::Set(name, value) => Expr::Set(name, self.desugar_boxed(value)),
+            Expr::DefineProcedure(name, args, body) => {
+                Expr::DefineProcedure(name, args, self.desugar_boxed(body))
+            }
+            Expr::Do(variables, test, result, body) => {
+                let variables = variables
+                    .into_iter()
+                    .map(|(name, init, step)| {
+                        (
+                            name,
+                            self.desugar(init),
+                            step.map(|step| self.desugar(step)),
+                        )
+                    })
+                    .collect();
+                Expr::Do(
+                    variables,
+                    self.desugar_boxed(test),
+                    self.desugar_all(result),
+                    self.desugar_all(body),
+                )
+            }
+            Expr::Number(_)
+            | Expr::Real(_)
+            | Expr::Bignum(_)
+            | Expr::Boolean(_)
+            | Expr::Char(_)
+            | Expr::Symbol(_)
+            | Expr::String(_)
+            | Expr::Quote(_)
+            | Expr::Vector(_)
+            | Expr::Error => expr,
+            Expr::Quasiquote(_)
+            | Expr::DefineSyntax(..)
+            | Expr::LetSyntax(..)
+            | Expr::LetrecSyntax(..) => {
+                unreachable!("Macros are expanded before desugaring")
+            }
+        }
+    }
+
+    /// Clauses are nested from the last one, each clause is the alternative of the previous one
+    fn desugar_cond(&mut self, clauses: Vec<CondClause>, else_body: Option<Vec<Expr>>) -> Expr {
+        let mut result = else_body.map(|body| self.desugar_body(body));
+        for clause in clauses.into_iter().rev() {
+            let clause_expr = match clause {
+                CondClause::Test(test, body) if body.is_empty() => {
+                    let (temp, test) = self.bind_temp(test);
+                    let value = Expr::Symbol(temp.clone());
+                    let if_test = if_expr(Expr::Symbol(temp.clone()), value, result);
+                    let_expr(temp, test, if_test)
+                }
+                CondClause::Test(test, body) => {
+                    let test = self.desugar(test);
+                    let body = self.desugar_body(body);
+                    if_expr(test, body, result)
+                }
+                CondClause::Receiver(test, receiver) => {
+                    let (temp, test) = self.bind_temp(test);
+                    let receiver = self.desugar(receiver);
+                    let call = Expr::List(vec![receiver, Expr::Symbol(temp.clone())]);
+                    let if_test = if_expr(Expr::Symbol(temp.clone()), call, result);
+                    let_expr(temp, test, if_test)
+                }
+            };
+            result = Some(clause_expr);
+        }
+        result.expect("Cond expressions have at least one clause")
+    }
+
+    /// The key is evaluated once, and compared to each quoted clause datum with `eqv?`
+    fn desugar_case(
+        &mut self,
+        key: Expr,
+        clauses: Vec<(Vec<Expr>, Vec<Expr>)>,
+        else_body: Option<Vec<Expr>>,
+    ) -> Expr {
+        let (temp, key) = self.bind_temp(key);
+        let mut result = else_body.map(|body| self.desugar_body(body));
+        for (data, body) in clauses.into_iter().rev() {
+            let comparisons = data
+                .into_iter()
+                .map(|datum| {
+                    let datum = Expr::Quote(Box::new(datum));
+                    Expr::Procedure("eqv?".to_owned(), vec![Expr::Symbol(temp.clone()), datum])
+                })
+                .collect();
+            let test = self.desugar_or(comparisons);
+            let body = self.desugar_body(body);
+            result = Some(if_expr(test, body, result));
+        }
+        let result = result.expect("Case expressions have at least one clause");
+        let_expr(temp, key, result)
+    }
+
+    fn desugar_and(&mut self, mut exprs: Vec<Expr>) -> Expr {
+        match exprs.len() {
+            0 => Expr::Boolean(true),
+            1 => self.desugar(exprs.remove(0)),
+            _ => {
+                let rest = exprs.split_off(1);
+                let (temp, test) = self.bind_temp(exprs.remove(0));
+                let rest = self.desugar_and(rest);
+                let value = Expr::Symbol(temp.clone());
+                let if_test = if_expr(Expr::Symbol(temp.clone()), rest, Some(value));
+                let_expr(temp, test, if_test)
+            }
+        }
+    }
+
+    fn desugar_or(&mut self, mut exprs: Vec<Expr>) -> Expr {
+        match exprs.len() {
+            0 => Expr::Boolean(false),
+            1 => self.desugar(exprs.remove(0)),
+            _ => {
+                let rest = exprs.split_off(1);
+                let (temp, test) = self.bind_temp(exprs.remove(0));
+                let rest = self.desugar_or(rest);
+                let value = Expr::Symbol(temp.clone());
+                let if_test = if_expr(Expr::Symbol(temp.clone()), value, Some(rest));
+                let_expr(temp, test, if_test)
+            }
+        }
+    }
+
+    /// Desugars an expression whose value is used more than once, and names a temporary for it.
+    /// Temporary names aren't valid symbols, so they can't shadow user bindings
+    fn bind_temp(&mut self, expr: Expr) -> (String, Expr) {
+        self.temp_id += 1;
+        let temp = format!("#temp.{}", self.temp_id);
+        (temp, self.desugar(expr))
+    }
+
+    fn desugar_body(&mut self, body: Vec<Expr>) -> Expr {
+        let body = self.desugar_all(body);
+        sequence(body)
+    }
+
+    /// Desugars a boxed expression, reusing its box
+    fn desugar_boxed(&mut self, mut expr: Box<Expr>) -> Box<Expr> {
+        *expr = self.desugar(std::mem::replace(&mut *expr, Expr::Error));
+        expr
+    }
+
+    fn desugar_all(&mut self, exprs: Vec<Expr>) -> Vec<Expr> {
+        exprs.into_iter().map(|expr| self.desugar(expr)).collect()
+    }
+
+    fn desugar_bindings(&mut self, symbols: Vec<(String, Expr)>) -> Vec<(String, Expr)> {
+        symbols
+            .into_iter()
+            .map(|(name, expr)| (name, self.desugar(expr)))
+            .collect()
+    }
+}
+
+fn if_expr(test: Expr, consequent: Expr, alternative: Option<Expr>) -> Expr {
+    let mut args = vec![test, consequent];
+    args.extend(alternative);
+    Expr::Procedure("if".to_owned(), args)
+}
+
+fn let_expr(name: String, value: Expr, body: Expr) -> Expr {
+    Expr::LetProcedure(vec![(name, value)], Box::new(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Formals;
+
+    fn symbol(name: &str) -> Expr {
+        Expr::Symbol(name.to_owned())
+    }
+
+    fn call(name: &str, args: Vec<Expr>) -> Expr {
+        Expr::Procedure(name.to_owned(), args)
+    }
+
+    /// Unwraps `(let ((temp value)) (if temp consequent alternative))`
+    fn unwrap_temp_if(expr: Expr) -> (Expr, Vec<Expr>) {
+        let Expr::LetProcedure(mut bindings, body) = expr else {
+            panic!("Expected let, got {expr:?}");
+        };
+        assert_eq!(bindings.len(), 1);
+        let (temp, value) = bindings.remove(0);
+        assert!(temp.starts_with('#'));
+        let Expr::Procedure(name, args) = *body else {
+            panic!("Expected if, got {body:?}");
+        };
+        assert_eq!(name, "if");
+        assert!(matches!(&args[0], Expr::Symbol(test) if *test == temp));
+        (value, args)
+    }
+
+    #[test]
+    fn desugar_cond() {
+        let expr = Expr::Cond(
+            vec![
+                CondClause::Test(
+                    call("<", vec![symbol("x"), Expr::Number(0)]),
+                    vec![symbol("a")],
+                ),
+                CondClause::Test(symbol("y"), vec![symbol("b"), symbol("c")]),
+            ],
+            Some(vec![symbol("d")]),
+        );
+        let Expr::Procedure(name, args) = Desugarer::default().desugar(expr) else {
+            panic!("Expected if");
+        };
+        assert_eq!(name, "if");
+        assert!(matches!(&args[0], Expr::Procedure(name, _) if name == "<"));
+        assert!(matches!(&args[1], Expr::Symbol(name) if name == "a"));
+        let Expr::Procedure(name, args) = &args[2] else {
+            panic!("Expected nested if");
+        };
+        assert_eq!(name, "if");
+        assert!(
+            matches!(&args[1], Expr::Procedure(name, body) if name == "begin" && body.len() == 2)
+        );
+        assert!(matches!(&args[2], Expr::Symbol(name) if name == "d"));
+    }
+
+    #[test]
+    fn desugar_cond_without_else() {
+        let expr = Expr::Cond(vec![CondClause::Test(symbol("x"), vec![symbol("a")])], None);
+        let Expr::Procedure(name, args) = Desugarer::default().desugar(expr) else {
+            panic!("Expected if");
+        };
+        assert_eq!(name, "if");
+        assert_eq!(args.len(), 2);
+    }
+
+    #[test]
+    fn desugar_cond_test_only() {
+        let expr = Expr::Cond(
+            vec![CondClause::Test(symbol("x"), vec![])],
+            Some(vec![symbol("a")]),
+        );
+        let (value, args) = unwrap_temp_if(Desugarer::default().desugar(expr));
+        assert!(matches!(value, Expr::Symbol(name) if name == "x"));
+        assert!(matches!((&args[0], &args[1]), (Expr::Symbol(a), Expr::Symbol(b)) if a == b));
+        assert!(matches!(&args[2], Expr::Symbol(name) if name == "a"));
+    }
+
+    #[test]
+    fn desugar_cond_receiver() {
+        let expr = Expr::Cond(vec![CondClause::Receiver(symbol("x"), symbol("f"))], None);
+        let (value, args) = unwrap_temp_if(Desugarer::default().desugar(expr));
+        assert!(matches!(value, Expr::Symbol(name) if name == "x"));
+        let Expr::List(items) = &args[1] else {
+            panic!("Expected receiver call, got {:?}", args[1]);
+        };
+        assert!(matches!(&items[0], Expr::Symbol(name) if name == "f"));
+        assert!(matches!((&args[0], &items[1]), (Expr::Symbol(a), Expr::Symbol(b)) if a == b));
+        assert_eq!(args.len(), 2);
+    }
+
+    #[test]
+    fn desugar_case() {
+        let expr = Expr::Case(
+            Box::new(call("+", vec![Expr::Number(1), Expr::Number(2)])),
+            vec![
+                (vec![Expr::Number(1), Expr::Number(2)], vec![symbol("a")]),
+                (vec![Expr::Number(3)], vec![symbol("b")]),
+            ],
+            Some(vec![symbol("c")]),
+        );
+        let Expr::LetProcedure(bindings, body) = Desugarer::default().desugar(expr) else {
+            panic!("Expected let");
+        };
+        let key = &bindings[0].0;
+        assert!(matches!(&bindings[0].1, Expr::Procedure(name, _) if name == "+"));
+        let Expr::Procedure(name, args) = *body else {
+            panic!("Expected if");
+        };
+        assert_eq!(name, "if");
+        // Many data are compared with `or`
+        let (first_test, or_args) = unwrap_temp_if(args[0].clone());
+        assert!(
+            matches!(&first_test, Expr::Procedure(name, cmp) if name == "eqv?"
+            && matches!(&cmp[0], Expr::Symbol(temp) if temp == key))
+        );
+        assert!(matches!(&or_args[2], Expr::Procedure(name, _) if name == "eqv?"));
+        let Expr::Procedure(_, args) = &args[2] else {
+            panic!("Expected nested if");
+        };
+        assert!(matches!(&args[0], Expr::Procedure(name, _) if name == "eqv?"));
+        assert!(matches!(&args[1], Expr::Symbol(name) if name == "b"));
+        assert!(matches!(&args[2], Expr::Symbol(name) if name == "c"));
+    }
+
+    #[test]
+    fn desugar_and() {
+        let mut desugarer = Desugarer::default();
+        assert!(matches!(
+            desugarer.desugar(Expr::And(vec![])),
+            Expr::Boolean(true)
+        ));
+        assert!(matches!(
+            desugarer.desugar(Expr::And(vec![symbol("a")])),
+            Expr::Symbol(_)
+        ));
+
+        let expr = Expr::And(vec![symbol("a"), symbol("b"), symbol("c")]);
+        let (value, args) = unwrap_temp_if(desugarer.desugar(expr));
+        assert!(matches!(value, Expr::Symbol(name) if name == "a"));
+        // The false value is returned as is
+        assert!(matches!((&args[0], &args[2]), (Expr::Symbol(a), Expr::Symbol(b)) if a == b));
+        let (value, args) = unwrap_temp_if(args[1].clone());
+        assert!(matches!(value, Expr::Symbol(name) if name == "b"));
+        assert!(matches!(&args[1], Expr::Symbol(name) if name == "c"));
+    }
+
+    #[test]
+    fn desugar_or() {
+        let mut desugarer = Desugarer::default();
+        assert!(matches!(
+            desugarer.desugar(Expr::Or(vec![])),
+            Expr::Boolean(false)
+        ));
+
+        let expr = Expr::Or(vec![symbol("a"), symbol("b")]);
+        let (value, args) = unwrap_temp_if(desugarer.desugar(expr));
+        assert!(matches!(value, Expr::Symbol(name) if name == "a"));
+        assert!(matches!((&args[0], &args[1]), (Expr::Symbol(a), Expr::Symbol(b)) if a == b));
+        assert!(matches!(&args[2], Expr::Symbol(name) if name == "b"));
+    }
+
+    #[test]
+    fn desugar_or_temps_are_unique() {
+        let expr = Expr::Or(vec![symbol("a"), symbol("b"), symbol("c")]);
+        let (_, args) = unwrap_temp_if(Desugarer::default().desugar(expr));
+        let Expr::LetProcedure(inner, _) = &args[2] else {
+            panic!("Expected nested let");
+        };
+        let Expr::Symbol(outer) = &args[0] else {
+            panic!("Expected temp");
+        };
+        assert_ne!(outer, &inner[0].0);
+    }
+
+    #[test]
+    fn desugar_when() {
+        let expr = Expr::When(Box::new(symbol("x")), vec![symbol("a"), symbol("b")]);
+        let Expr::Procedure(name, args) = Desugarer::default().desugar(expr) else {
+            panic!("Expected if");
+        };
+        assert_eq!(name, "if");
+        assert_eq!(args.len(), 2);
+        assert!(matches!(&args[1], Expr::Procedure(name, _) if name == "begin"));
+    }
+
+    #[test]
+    fn desugar_unless() {
+        let expr = Expr::Unless(Box::new(symbol("x")), vec![symbol("a")]);
+        let (value, args) = unwrap_temp_if(Desugarer::default().desugar(expr));
+        assert!(matches!(value, Expr::Symbol(name) if name == "x"));
+        assert!(matches!(&args[2], Expr::Symbol(name) if name == "a"));
+    }
+
+    #[test]
+    fn desugar_nested_forms() {
+        let expr = Expr::Lambda(
+            Formals::new(vec!["x".to_owned()]),
+            Box::new(Expr::When(
+                Box::new(Expr::And(vec![symbol("x")])),
+                vec![symbol("x")],
+            )),
+        );
+        let Expr::Lambda(_, body) = Desugarer::default().desugar(expr) else {
+            panic!("Expected lambda");
+        };
+        let Expr::Procedure(name, args) = *body else {
+            panic!("Expected if");
+        };
+        assert_eq!(name, "if");
+        assert!(matches!(&args[0], Expr::Symbol(name) if name == "x"));
+    }
+}
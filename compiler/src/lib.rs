@@ -3,3 +3,6 @@ extern crate core;
 #[allow(clippy::missing_safety_doc)]
 pub mod ast;
 pub mod backend;
+pub mod comments;
+pub mod desugar;
+pub mod macros;
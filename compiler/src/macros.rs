@@ -0,0 +1,1697 @@
+use crate::ast::{sequence, CondClause, Expr, Formals, SyntaxRules};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+
+/// Names of the forms parsed by the grammar, the expander rebuilds them from expanded data
+const SPECIAL_FORMS: [&str; 19] = [
+    "quote",
+    "quasiquote",
+    "let",
+    "let*",
+    "letrec",
+    "letrec*",
+    "lambda",
+    "define",
+    "set!",
+    "do",
+    "cond",
+    "case",
+    "and",
+    "or",
+    "when",
+    "unless",
+    "define-syntax",
+    "let-syntax",
+    "letrec-syntax",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum SyntaxError {
+    /// Special form or macro use that doesn't match its syntax, as written
+    IllFormed(String),
+    KeywordAsExpression(String),
+    /// Definition out of the top level and after the expressions of a body
+    MisplacedDefinition(String),
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxError::IllFormed(form) => write!(f, "Ill-formed special form: {form}"),
+            SyntaxError::KeywordAsExpression(name) => {
+                write!(
+                    f,
+                    "Syntactic keyword may not be used as an expression: {name}"
+                )
+            }
+            SyntaxError::MisplacedDefinition(name) => {
+                write!(
+                    f,
+                    "Definition of {name} is only allowed at top level or at the start of a body"
+                )
+            }
+        }
+    }
+}
+
+struct Macro {
+    rules: SyntaxRules,
+    /// Scope of the definition, where identifiers introduced by the templates are resolved
+    scope: Rc<Scope>,
+}
+
+#[derive(Clone)]
+enum Binding {
+    /// Local variable and its unique name in the expanded code
+    Variable(String),
+    Macro(Rc<Macro>),
+}
+
+#[derive(Default)]
+struct Scope {
+    bindings: RefCell<HashMap<String, Binding>>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    fn child(parent: &Rc<Scope>) -> Rc<Scope> {
+        Rc::new(Scope {
+            bindings: RefCell::default(),
+            parent: Some(parent.clone()),
+        })
+    }
+
+    fn bind(&self, id: &str, binding: Binding) {
+        self.bindings.borrow_mut().insert(id.to_owned(), binding);
+    }
+
+    fn lookup(&self, id: &str) -> Option<Binding> {
+        match self.bindings.borrow().get(id) {
+            Some(binding) => Some(binding.clone()),
+            None => self.parent.as_ref()?.lookup(id),
+        }
+    }
+}
+
+/// Pattern variable matched by a part of the macro use, or by each repetition of an ellipsis
+#[derive(Clone)]
+enum Match {
+    One(Expr),
+    Many(Vec<Match>),
+}
+
+/// Expands `syntax-rules` macros before desugaring.
+///
+/// Identifiers introduced by a template are renamed to aliases like `name#3`, which are resolved
+/// in the scope of the macro definition. Local variables are renamed the same way, so that
+/// neither the macro use nor the template capture each other's variables. Global variables keep
+/// their names
+pub struct Expander {
+    global: Rc<Scope>,
+    /// Identifier each alias stands for, and the scope where it is resolved
+    aliases: HashMap<String, (String, Rc<Scope>)>,
+    /// Empty scope resolving the runtime procedures called by expansions
+    primitives: Rc<Scope>,
+    next_id: usize,
+}
+
+impl Default for Expander {
+    fn default() -> Self {
+        Expander {
+            global: Rc::new(Scope::default()),
+            aliases: HashMap::new(),
+            primitives: Rc::new(Scope::default()),
+            next_id: 0,
+        }
+    }
+}
+
+impl Expander {
+    /// Parses and expands the top level data in order, syntax definitions are removed from the
+    /// module
+    pub fn expand_module(&mut self, data: Vec<Expr>) -> Result<Vec<Expr>, SyntaxError> {
+        let mut expanded = Vec::new();
+        for datum in data {
+            let expr = self.parse(&datum)?;
+            self.expand_toplevel(expr, &mut expanded)?;
+        }
+        Ok(expanded)
+    }
+
+    /// Forms of a top level `begin` are spliced into the module, so they can be definitions
+    fn expand_toplevel(&mut self, expr: Expr, expanded: &mut Vec<Expr>) -> Result<(), SyntaxError> {
+        let global = self.global.clone();
+        match expr {
+            Expr::DefineSyntax(name, rules) => {
+                let syntax = self.make_macro(rules, &global);
+                global.bind(&name, syntax);
+            }
+            Expr::Procedure(name, args) => match self.resolve(&name, &global) {
+                Some(Binding::Macro(syntax)) => {
+                    let expansion =
+                        self.expand_use(&syntax, Expr::Procedure(name, args), &global)?;
+                    self.expand_toplevel(expansion, expanded)?;
+                }
+                None if self.global_name(&name) == "begin" => {
+                    for arg in args {
+                        let arg = self.parse(&arg)?;
+                        self.expand_toplevel(arg, expanded)?;
+                    }
+                }
+                _ => expanded.push(self.expand(Expr::Procedure(name, args), &global)?),
+            },
+            _ => expanded.push(self.expand(expr, &global)?),
+        }
+        Ok(())
+    }
+
+    fn expand(&mut self, expr: Expr, scope: &Rc<Scope>) -> Result<Expr, SyntaxError> {
+        let expr = match expr {
+            Expr::Symbol(id) => match self.resolve(&id, scope) {
+                Some(Binding::Variable(name)) => Expr::Symbol(name),
+                Some(Binding::Macro(_)) => {
+                    return Err(SyntaxError::KeywordAsExpression(self.global_name(&id)))
+                }
+                None => Expr::Symbol(self.global_name(&id)),
+            },
+            Expr::Quote(datum) => Expr::Quote(Box::new(self.strip(&datum))),
+            Expr::Quasiquote(template) => {
+                let construction = self.quasiquote(&template, 0)?;
+                self.expand(construction, scope)?
+            }
+            Expr::Vector(items) => {
+                Expr::Vector(items.iter().map(|item| self.strip(item)).collect())
+            }
+            Expr::Procedure(name, args) => match self.resolve(&name, scope) {
+                Some(Binding::Macro(syntax)) => {
+                    let expansion = self.expand_use(&syntax, Expr::Procedure(name, args), scope)?;
+                    self.expand(expansion, scope)?
+                }
+                Some(Binding::Variable(name)) => {
+                    Expr::Procedure(name, self.expand_operands(args, scope)?)
+                }
+                None => {
+                    Expr::Procedure(self.global_name(&name), self.expand_operands(args, scope)?)
+                }
+            },
+            Expr::List(items) if items.is_empty() => {
+                return Err(SyntaxError::IllFormed("()".to_owned()))
+            }
+            Expr::List(items) => Expr::List(self.expand_all(items, scope)?),
+            Expr::LetProcedure(bindings, body) => {
+                let inits = self.expand_inits(&bindings, scope)?;
+                let inner = Scope::child(scope);
+                let bindings = self.bind_all(bindings, inits, &inner);
+                Expr::LetProcedure(bindings, self.expand_body(*body, &inner)?)
+            }
+            Expr::NamedLetProcedure(name, bindings, body) => {
+                let inits = self.expand_inits(&bindings, scope)?;
+                let inner = Scope::child(scope);
+                let name = self.bind(&name, &inner);
+                let bindings = self.bind_all(bindings, inits, &inner);
+                Expr::NamedLetProcedure(name, bindings, self.expand_body(*body, &inner)?)
+            }
+            Expr::LetStarProcedure(bindings, body) => {
+                let mut inner = scope.clone();
+                let mut expanded = Vec::new();
+                for (name, init) in bindings {
+                    let init = self.expand(init, &inner)?;
+                    inner = Scope::child(&inner);
+                    expanded.push((self.bind(&name, &inner), init));
+                }
+                Expr::LetStarProcedure(expanded, self.expand_body(*body, &inner)?)
+            }
+            Expr::LetrecProcedure(bindings, body) => {
+                let (bindings, body) = self.expand_letrec(bindings, *body, scope)?;
+                Expr::LetrecProcedure(bindings, body)
+            }
+            Expr::LetrecStarProcedure(bindings, body) => {
+                let (bindings, body) = self.expand_letrec(bindings, *body, scope)?;
+                Expr::LetrecStarProcedure(bindings, body)
+            }
+            Expr::Lambda(formals, body) => {
+                let inner = Scope::child(scope);
+                let formals = self.bind_formals(formals, &inner);
+                Expr::Lambda(formals, self.expand_body(*body, &inner)?)
+            }
+            Expr::DefineExpr(name, value) => {
+                let name = self.define(&name, scope)?;
+                Expr::DefineExpr(name, self.expand_boxed(*value, scope)?)
+            }
+            Expr::DefineProcedure(name, formals, body) => {
+                let name = self.define(&name, scope)?;
+                let inner = Scope::child(scope);
+                let formals = self.bind_formals(formals, &inner);
+                Expr::DefineProcedure(name, formals, self.expand_body(*body, &inner)?)
+            }
+            Expr::Set(name, value) => {
+                let name = match self.resolve(&name, scope) {
+                    Some(Binding::Variable(name)) => name,
+                    Some(Binding::Macro(_)) => {
+                        return Err(SyntaxError::KeywordAsExpression(self.global_name(&name)))
+                    }
+                    None => self.global_name(&name),
+                };
+                Expr::Set(name, self.expand_boxed(*value, scope)?)
+            }
+            Expr::Do(variables, test, result, body) => {
+                let mut inits = Vec::new();
+                for (_, init, _) in &variables {
+                    inits.push(self.expand(init.clone(), scope)?);
+                }
+                let inner = Scope::child(scope);
+                let names = variables
+                    .iter()
+                    .map(|(name, _, _)| self.bind(name, &inner))
+                    .collect::<Vec<_>>();
+                let mut expanded = Vec::new();
+                for ((name, init), (_, _, step)) in names.into_iter().zip(inits).zip(variables) {
+                    let step = step.map(|step| self.expand(step, &inner)).transpose()?;
+                    expanded.push((name, init, step));
+                }
+                Expr::Do(
+                    expanded,
+                    self.expand_boxed(*test, &inner)?,
+                    self.expand_all(result, &inner)?,
+                    self.expand_all(body, &inner)?,
+                )
+            }
+            Expr::Cond(clauses, else_body) => {
+                let mut expanded = Vec::new();
+                for clause in clauses {
+                    expanded.push(match clause {
+                        CondClause::Test(test, body) => CondClause::Test(
+                            self.expand(test, scope)?,
+                            self.expand_all(body, scope)?,
+                        ),
+                        CondClause::Receiver(test, receiver) => CondClause::Receiver(
+                            self.expand(test, scope)?,
+                            self.expand(receiver, scope)?,
+                        ),
+                    });
+                }
+                let else_body = else_body
+                    .map(|body| self.expand_all(body, scope))
+                    .transpose()?;
+                Expr::Cond(expanded, else_body)
+            }
+            Expr::Case(key, clauses, else_body) => {
+                let key = self.expand_boxed(*key, scope)?;
+                let mut expanded = Vec::new();
+                for (data, body) in clauses {
+                    let data = data.iter().map(|datum| self.strip(datum)).collect();
+                    expanded.push((data, self.expand_all(body, scope)?));
+                }
+                let else_body = else_body
+                    .map(|body| self.expand_all(body, scope))
+                    .transpose()?;
+                Expr::Case(key, expanded, else_body)
+            }
+            Expr::And(exprs) => Expr::And(self.expand_all(exprs, scope)?),
+            Expr::Or(exprs) => Expr::Or(self.expand_all(exprs, scope)?),
+            Expr::When(test, body) => Expr::When(
+                self.expand_boxed(*test, scope)?,
+                self.expand_all(body, scope)?,
+            ),
+            Expr::Unless(test, body) => Expr::Unless(
+                self.expand_boxed(*test, scope)?,
+                self.expand_all(body, scope)?,
+            ),
+            Expr::DefineSyntax(name, _) => {
+                return Err(SyntaxError::MisplacedDefinition(self.global_name(&name)))
+            }
+            Expr::LetSyntax(bindings, body) => {
+                let inner = Scope::child(scope);
+                for (name, rules) in bindings {
+                    let syntax = self.make_macro(rules, scope);
+                    inner.bind(&name, syntax);
+                }
+                *self.expand_body(*body, &inner)?
+            }
+            Expr::LetrecSyntax(bindings, body) => {
+                let inner = Scope::child(scope);
+                for (name, rules) in bindings {
+                    let syntax = self.make_macro(rules, &inner);
+                    inner.bind(&name, syntax);
+                }
+                *self.expand_body(*body, &inner)?
+            }
+            Expr::Number(_)
+            | Expr::Real(_)
+            | Expr::Bignum(_)
+            | Expr::Boolean(_)
+            | Expr::Char(_)
+            | Expr::String(_)
+            | Expr::Error => expr,
+        };
+        Ok(expr)
+    }
+
+    fn expand_all(
+        &mut self,
+        exprs: Vec<Expr>,
+        scope: &Rc<Scope>,
+    ) -> Result<Vec<Expr>, SyntaxError> {
+        exprs
+            .into_iter()
+            .map(|expr| self.expand(expr, scope))
+            .collect()
+    }
+
+    /// Operands of calls are parsed as data, since they could have been operands of a macro use
+    fn expand_operands(
+        &mut self,
+        operands: Vec<Expr>,
+        scope: &Rc<Scope>,
+    ) -> Result<Vec<Expr>, SyntaxError> {
+        let operands = self.parse_all(&operands)?;
+        self.expand_all(operands, scope)
+    }
+
+    fn expand_boxed(&mut self, expr: Expr, scope: &Rc<Scope>) -> Result<Box<Expr>, SyntaxError> {
+        self.expand(expr, scope).map(Box::new)
+    }
+
+    /// Definitions at the start of the body are bound like `letrec*` around the expressions
+    /// following them. Macro uses are expanded first, since they may produce definitions, and
+    /// `begin` forms are spliced into the body
+    fn expand_body(&mut self, body: Expr, scope: &Rc<Scope>) -> Result<Box<Expr>, SyntaxError> {
+        let inner = Scope::child(scope);
+        let mut forms = VecDeque::from([body.clone()]);
+        let mut definitions = Vec::new();
+        let mut exprs = Vec::new();
+        while let Some(form) = forms.pop_front() {
+            let form = match form {
+                Expr::Procedure(name, args) => match self.resolve(&name, &inner) {
+                    Some(Binding::Macro(syntax)) => {
+                        let expansion =
+                            self.expand_use(&syntax, Expr::Procedure(name, args), &inner)?;
+                        forms.push_front(expansion);
+                        continue;
+                    }
+                    None if exprs.is_empty() && self.global_name(&name) == "begin" => {
+                        for arg in args.iter().rev() {
+                            forms.push_front(self.parse(arg)?);
+                        }
+                        continue;
+                    }
+                    _ => Expr::Procedure(name, args),
+                },
+                form => form,
+            };
+            match form {
+                Expr::DefineExpr(name, value) if exprs.is_empty() => {
+                    definitions.push((self.bind(&name, &inner), *value));
+                }
+                Expr::DefineProcedure(name, formals, body) if exprs.is_empty() => {
+                    definitions.push((self.bind(&name, &inner), Expr::Lambda(formals, body)));
+                }
+                Expr::DefineSyntax(name, rules) if exprs.is_empty() => {
+                    let syntax = self.make_macro(rules, &inner);
+                    inner.bind(&name, syntax);
+                }
+                Expr::DefineExpr(name, _)
+                | Expr::DefineProcedure(name, _, _)
+                | Expr::DefineSyntax(name, _) => {
+                    return Err(SyntaxError::MisplacedDefinition(self.global_name(&name)))
+                }
+                form => exprs.push(form),
+            }
+        }
+        if exprs.is_empty() {
+            return Err(self.ill_formed(&to_datum(body)));
+        }
+        let mut bindings = Vec::new();
+        for (name, value) in definitions {
+            bindings.push((name, self.expand(value, &inner)?));
+        }
+        let body = sequence(self.expand_all(exprs, &inner)?);
+        if bindings.is_empty() {
+            Ok(Box::new(body))
+        } else {
+            Ok(Box::new(Expr::LetrecStarProcedure(
+                bindings,
+                Box::new(body),
+            )))
+        }
+    }
+
+    fn expand_inits(
+        &mut self,
+        bindings: &[(String, Expr)],
+        scope: &Rc<Scope>,
+    ) -> Result<Vec<Expr>, SyntaxError> {
+        bindings
+            .iter()
+            .map(|(_, init)| self.expand(init.clone(), scope))
+            .collect()
+    }
+
+    /// Variables of `letrec` are visible in their own inits
+    #[allow(clippy::type_complexity)]
+    fn expand_letrec(
+        &mut self,
+        bindings: Vec<(String, Expr)>,
+        body: Expr,
+        scope: &Rc<Scope>,
+    ) -> Result<(Vec<(String, Expr)>, Box<Expr>), SyntaxError> {
+        let inner = Scope::child(scope);
+        let names = bindings
+            .iter()
+            .map(|(name, _)| self.bind(name, &inner))
+            .collect::<Vec<_>>();
+        let inits = self.expand_inits(&bindings, &inner)?;
+        Ok((
+            names.into_iter().zip(inits).collect(),
+            self.expand_body(body, &inner)?,
+        ))
+    }
+
+    fn bind_all(
+        &mut self,
+        bindings: Vec<(String, Expr)>,
+        inits: Vec<Expr>,
+        scope: &Rc<Scope>,
+    ) -> Vec<(String, Expr)> {
+        bindings
+            .into_iter()
+            .zip(inits)
+            .map(|((name, _), init)| (self.bind(&name, scope), init))
+            .collect()
+    }
+
+    fn bind_formals(&mut self, formals: Formals, scope: &Rc<Scope>) -> Formals {
+        Formals {
+            args: formals
+                .args
+                .iter()
+                .map(|arg| self.bind(arg, scope))
+                .collect(),
+            rest: formals.rest.map(|rest| self.bind(&rest, scope)),
+        }
+    }
+
+    /// Binds a local variable to a new unique name
+    fn bind(&mut self, id: &str, scope: &Rc<Scope>) -> String {
+        self.next_id += 1;
+        let name = format!("{}#{}", self.global_name(id), self.next_id);
+        scope.bind(id, Binding::Variable(name.clone()));
+        name
+    }
+
+    /// Name of a global variable definition, which replaces a macro with the same name.
+    /// Internal definitions are bound by their body instead
+    fn define(&mut self, id: &str, scope: &Rc<Scope>) -> Result<String, SyntaxError> {
+        if !Rc::ptr_eq(scope, &self.global) {
+            return Err(SyntaxError::MisplacedDefinition(self.global_name(id)));
+        }
+        self.global.bindings.borrow_mut().remove(id);
+        Ok(self.global_name(id))
+    }
+
+    fn make_macro(&self, mut rules: SyntaxRules, scope: &Rc<Scope>) -> Binding {
+        rules.ellipsis = self.global_name(&rules.ellipsis);
+        Binding::Macro(Rc::new(Macro {
+            rules,
+            scope: scope.clone(),
+        }))
+    }
+
+    /// Local binding of the identifier, aliases are resolved where their macro was defined.
+    /// Identifiers without binding are global variables
+    fn resolve(&self, id: &str, scope: &Rc<Scope>) -> Option<Binding> {
+        let mut id = id;
+        let mut scope = scope;
+        loop {
+            if let Some(binding) = scope.lookup(id) {
+                return Some(binding);
+            }
+            let (original, definition) = self.aliases.get(id)?;
+            id = original;
+            scope = definition;
+        }
+    }
+
+    /// Identifier written in the source for an alias
+    fn global_name(&self, id: &str) -> String {
+        let mut id = id;
+        while let Some((original, _)) = self.aliases.get(id) {
+            id = original;
+        }
+        id.to_owned()
+    }
+
+    /// Quoted data are written with the names of the source
+    fn strip(&self, datum: &Expr) -> Expr {
+        match datum {
+            Expr::Symbol(id) => Expr::Symbol(self.global_name(id)),
+            Expr::List(items) => Expr::List(items.iter().map(|item| self.strip(item)).collect()),
+            Expr::Vector(items) => {
+                Expr::Vector(items.iter().map(|item| self.strip(item)).collect())
+            }
+            _ => datum.clone(),
+        }
+    }
+
+    /// Transcribes the template of the first rule matching the use, the result isn't expanded
+    fn expand_use(
+        &mut self,
+        syntax: &Macro,
+        form: Expr,
+        scope: &Rc<Scope>,
+    ) -> Result<Expr, SyntaxError> {
+        let form = to_datum(form);
+        let Expr::List(forms) = &form else {
+            unreachable!("Macro uses are lists")
+        };
+        for (pattern, template) in &syntax.rules.rules {
+            // The keyword position of the pattern is ignored
+            let Expr::List(patterns) = pattern else {
+                continue;
+            };
+            let mut bindings = HashMap::new();
+            if !patterns.is_empty()
+                && self.match_sequence(&patterns[1..], &forms[1..], syntax, scope, &mut bindings)
+            {
+                let ellipsis = Some(syntax.rules.ellipsis.as_str());
+                let mut renames = HashMap::new();
+                let expansion =
+                    self.transcribe(template, &bindings, syntax, ellipsis, &mut renames)?;
+                return self.parse(&expansion);
+            }
+        }
+        Err(self.ill_formed(&form))
+    }
+
+    fn is_ellipsis(&self, datum: &Expr, syntax: &Macro) -> bool {
+        matches!(datum, Expr::Symbol(id) if self.global_name(id) == syntax.rules.ellipsis)
+    }
+
+    fn match_pattern(
+        &self,
+        pattern: &Expr,
+        form: &Expr,
+        syntax: &Macro,
+        scope: &Rc<Scope>,
+        bindings: &mut HashMap<String, Match>,
+    ) -> bool {
+        match pattern {
+            Expr::Symbol(id) if syntax.rules.literals.contains(id) => {
+                matches!(form, Expr::Symbol(form_id) if self.same_binding(form_id, scope, id, &syntax.scope))
+            }
+            Expr::Symbol(id) if self.global_name(id) == "_" => true,
+            Expr::Symbol(id) => {
+                bindings.insert(id.clone(), Match::One(form.clone()));
+                true
+            }
+            Expr::List(patterns) => {
+                matches!(form, Expr::List(forms) if self.match_sequence(patterns, forms, syntax, scope, bindings))
+            }
+            Expr::Vector(patterns) => {
+                matches!(form, Expr::Vector(forms) if self.match_sequence(patterns, forms, syntax, scope, bindings))
+            }
+            _ => same_atom(pattern, form),
+        }
+    }
+
+    /// Matches the items of a list or vector, an ellipsis repeats the pattern before it and can
+    /// be followed by a fixed number of patterns. The pattern after the dot of a dotted pattern
+    /// matches the remaining items as a list, or the tail of the form after an ellipsis
+    fn match_sequence(
+        &self,
+        patterns: &[Expr],
+        forms: &[Expr],
+        syntax: &Macro,
+        scope: &Rc<Scope>,
+        bindings: &mut HashMap<String, Match>,
+    ) -> bool {
+        let (patterns, pattern_tail) = self.split_dotted(patterns);
+        let (forms, form_tail) = self.split_dotted(forms);
+        let ellipsis = patterns
+            .iter()
+            .position(|pattern| self.is_ellipsis(pattern, syntax));
+        let Some(ellipsis) = ellipsis.filter(|&ellipsis| ellipsis > 0) else {
+            let Some(pattern_tail) = pattern_tail else {
+                return form_tail.is_none()
+                    && self.match_all(patterns, forms, syntax, scope, bindings);
+            };
+            if forms.len() < patterns.len() {
+                return false;
+            }
+            let (init, rest) = forms.split_at(patterns.len());
+            let rest = dotted_list(rest, form_tail);
+            return self.match_all(patterns, init, syntax, scope, bindings)
+                && self.match_pattern(pattern_tail, &rest, syntax, scope, bindings);
+        };
+        let tail_matches = match pattern_tail {
+            Some(pattern_tail) => {
+                let rest = dotted_list(&[], form_tail);
+                self.match_pattern(pattern_tail, &rest, syntax, scope, bindings)
+            }
+            None => form_tail.is_none(),
+        };
+        let before = &patterns[..ellipsis - 1];
+        let after = &patterns[ellipsis + 1..];
+        if !tail_matches || forms.len() < before.len() + after.len() {
+            return false;
+        }
+        let repeated_end = forms.len() - after.len();
+        if !self.match_all(before, &forms[..before.len()], syntax, scope, bindings)
+            || !self.match_all(after, &forms[repeated_end..], syntax, scope, bindings)
+        {
+            return false;
+        }
+        let repeated = &patterns[ellipsis - 1];
+        let mut repetitions = Vec::new();
+        for form in &forms[before.len()..repeated_end] {
+            let mut repetition = HashMap::new();
+            if !self.match_pattern(repeated, form, syntax, scope, &mut repetition) {
+                return false;
+            }
+            repetitions.push(repetition);
+        }
+        for variable in self.pattern_variables(repeated, syntax) {
+            let matches = repetitions
+                .iter_mut()
+                .filter_map(|repetition| repetition.remove(&variable))
+                .collect();
+            bindings.insert(variable, Match::Many(matches));
+        }
+        true
+    }
+
+    fn match_all(
+        &self,
+        patterns: &[Expr],
+        forms: &[Expr],
+        syntax: &Macro,
+        scope: &Rc<Scope>,
+        bindings: &mut HashMap<String, Match>,
+    ) -> bool {
+        patterns.len() == forms.len()
+            && patterns
+                .iter()
+                .zip(forms)
+                .all(|(pattern, form)| self.match_pattern(pattern, form, syntax, scope, bindings))
+    }
+
+    /// Items of a list before its dot, and the datum after the dot
+    fn split_dotted<'a>(&self, items: &'a [Expr]) -> (&'a [Expr], Option<&'a Expr>) {
+        match items {
+            [init @ .., Expr::Symbol(dot), last]
+                if !init.is_empty() && self.global_name(dot) == "." =>
+            {
+                (init, Some(last))
+            }
+            _ => (items, None),
+        }
+    }
+
+    fn pattern_variables(&self, pattern: &Expr, syntax: &Macro) -> Vec<String> {
+        match pattern {
+            Expr::Symbol(id)
+                if !syntax.rules.literals.contains(id)
+                    && !self.is_ellipsis(pattern, syntax)
+                    && !["_", "."].contains(&self.global_name(id).as_str()) =>
+            {
+                vec![id.clone()]
+            }
+            Expr::List(patterns) | Expr::Vector(patterns) => patterns
+                .iter()
+                .flat_map(|pattern| self.pattern_variables(pattern, syntax))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether an identifier of the use means the same as a literal of the macro
+    fn same_binding(
+        &self,
+        id: &str,
+        scope: &Rc<Scope>,
+        literal: &str,
+        literal_scope: &Rc<Scope>,
+    ) -> bool {
+        match (
+            self.resolve(id, scope),
+            self.resolve(literal, literal_scope),
+        ) {
+            (None, None) => self.global_name(id) == self.global_name(literal),
+            (Some(Binding::Variable(name)), Some(Binding::Variable(literal_name))) => {
+                name == literal_name
+            }
+            (Some(Binding::Macro(syntax)), Some(Binding::Macro(literal_syntax))) => {
+                Rc::ptr_eq(&syntax, &literal_syntax)
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces the pattern variables of the template, the other identifiers are renamed to an
+    /// alias, the same one for every occurrence in this expansion. `(... template)` escapes
+    /// the ellipsis in the template
+    fn transcribe(
+        &mut self,
+        template: &Expr,
+        bindings: &HashMap<String, Match>,
+        syntax: &Macro,
+        ellipsis: Option<&str>,
+        renames: &mut HashMap<String, String>,
+    ) -> Result<Expr, SyntaxError> {
+        match template {
+            Expr::Symbol(id) => match bindings.get(id) {
+                Some(Match::One(form)) => Ok(form.clone()),
+                Some(Match::Many(_)) => Err(self.ill_formed(template)),
+                // The dot of dotted lists isn't an identifier
+                None if self.global_name(id) == "." => Ok(symbol(".")),
+                None => Ok(Expr::Symbol(self.alias(id, syntax, renames))),
+            },
+            Expr::List(items) => match items.as_slice() {
+                [Expr::Symbol(id), escaped] if ellipsis == Some(self.global_name(id).as_str()) => {
+                    self.transcribe(escaped, bindings, syntax, None, renames)
+                }
+                _ => {
+                    let items =
+                        self.transcribe_sequence(items, bindings, syntax, ellipsis, renames)?;
+                    Ok(Expr::List(self.join_dotted(items)))
+                }
+            },
+            Expr::Vector(items) => Ok(Expr::Vector(
+                self.transcribe_sequence(items, bindings, syntax, ellipsis, renames)?,
+            )),
+            _ => Ok(template.clone()),
+        }
+    }
+
+    /// Splices the list after the dot of a transcribed list, `(a . (b c))` is `(a b c)`
+    fn join_dotted(&self, mut items: Vec<Expr>) -> Vec<Expr> {
+        loop {
+            let (init, tail) = self.split_dotted(&items);
+            let Some(Expr::List(tail)) = tail else {
+                return items;
+            };
+            items = init.iter().chain(tail).cloned().collect();
+        }
+    }
+
+    fn transcribe_sequence(
+        &mut self,
+        items: &[Expr],
+        bindings: &HashMap<String, Match>,
+        syntax: &Macro,
+        ellipsis: Option<&str>,
+        renames: &mut HashMap<String, String>,
+    ) -> Result<Vec<Expr>, SyntaxError> {
+        let mut transcribed = Vec::new();
+        let mut index = 0;
+        while index < items.len() {
+            let depth = items[index + 1..]
+                .iter()
+                .take_while(|item| {
+                    matches!((item, ellipsis), (Expr::Symbol(id), Some(ellipsis)) if self.global_name(id) == ellipsis)
+                })
+                .count();
+            let item = &items[index];
+            if depth == 0 {
+                transcribed.push(self.transcribe(item, bindings, syntax, ellipsis, renames)?);
+            } else {
+                let repeated =
+                    self.transcribe_repeated(item, depth, bindings, syntax, ellipsis, renames)?;
+                transcribed.extend(repeated);
+            }
+            index += 1 + depth;
+        }
+        Ok(transcribed)
+    }
+
+    /// Transcribes the template once for each match of the repeated pattern variables in it,
+    /// nested ellipses flatten the repetitions
+    fn transcribe_repeated(
+        &mut self,
+        template: &Expr,
+        depth: usize,
+        bindings: &HashMap<String, Match>,
+        syntax: &Macro,
+        ellipsis: Option<&str>,
+        renames: &mut HashMap<String, String>,
+    ) -> Result<Vec<Expr>, SyntaxError> {
+        let mut variables = Vec::new();
+        template_symbols(template, &mut variables);
+        variables.retain(|variable| matches!(bindings.get(variable), Some(Match::Many(_))));
+        let lengths = variables
+            .iter()
+            .filter_map(|variable| match bindings.get(variable) {
+                Some(Match::Many(matches)) => Some(matches.len()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let Some(&length) = lengths.first() else {
+            return Err(self.ill_formed(template));
+        };
+        if lengths.iter().any(|&other| other != length) {
+            return Err(self.ill_formed(template));
+        }
+        let mut transcribed = Vec::new();
+        for index in 0..length {
+            let mut repetition = bindings.clone();
+            for variable in &variables {
+                if let Some(Match::Many(matches)) = bindings.get(variable) {
+                    repetition.insert(variable.clone(), matches[index].clone());
+                }
+            }
+            if depth > 1 {
+                let nested = self.transcribe_repeated(
+                    template,
+                    depth - 1,
+                    &repetition,
+                    syntax,
+                    ellipsis,
+                    renames,
+                )?;
+                transcribed.extend(nested);
+            } else {
+                transcribed.push(self.transcribe(
+                    template,
+                    &repetition,
+                    syntax,
+                    ellipsis,
+                    renames,
+                )?);
+            }
+        }
+        Ok(transcribed)
+    }
+
+    fn alias(&mut self, id: &str, syntax: &Macro, renames: &mut HashMap<String, String>) -> String {
+        if let Some(alias) = renames.get(id) {
+            return alias.clone();
+        }
+        self.next_id += 1;
+        let alias = format!("{}#{}", self.global_name(id), self.next_id);
+        self.aliases
+            .insert(alias.clone(), (id.to_owned(), syntax.scope.clone()));
+        renames.insert(id.to_owned(), alias.clone());
+        alias
+    }
+
+    /// Alias of a runtime procedure, local bindings of the same name don't capture it
+    fn primitive(&mut self, name: &str) -> String {
+        self.next_id += 1;
+        let alias = format!("{name}#{}", self.next_id);
+        self.aliases
+            .insert(alias.clone(), (name.to_owned(), self.primitives.clone()));
+        alias
+    }
+
+    /// Code building the data of a backquote template. Parts without unquote are quoted, and
+    /// `depth` counts the quasiquotes nested in the template, whose unquotes are kept as data
+    fn quasiquote(&mut self, template: &Expr, depth: usize) -> Result<Expr, SyntaxError> {
+        if !self.has_unquote(template, depth) {
+            return Ok(Expr::Quote(Box::new(template.clone())));
+        }
+        match template {
+            Expr::List(items) => match self.quasiquote_keyword(template) {
+                Some(("unquote", expr)) if depth == 0 => self.parse(expr),
+                Some(("unquote-splicing", _)) if depth == 0 => Err(self.ill_formed(template)),
+                Some(("unquote" | "unquote-splicing", _)) => self.quasiquote_list(items, depth - 1),
+                Some(("quasiquote", _)) => self.quasiquote_list(items, depth + 1),
+                _ => self.quasiquote_list(items, depth),
+            },
+            Expr::Vector(items) => Ok(Expr::Procedure(
+                self.primitive("list->vector"),
+                vec![self.quasiquote_list(items, depth)?],
+            )),
+            _ => unreachable!("Atoms have no unquote"),
+        }
+    }
+
+    /// Conses the items onto the rest of the list, spliced items are appended. Dotted lists end
+    /// with their last datum instead of the empty list
+    fn quasiquote_list(&mut self, items: &[Expr], depth: usize) -> Result<Expr, SyntaxError> {
+        let (items, mut list) = match self.split_dotted(items) {
+            (init, Some(last)) => (init, self.quasiquote(last, depth)?),
+            (items, None) => (items, Expr::Quote(Box::new(Expr::List(vec![])))),
+        };
+        for item in items.iter().rev() {
+            list = match self.quasiquote_keyword(item) {
+                Some(("unquote-splicing", expr)) if depth == 0 => {
+                    Expr::Procedure(self.primitive("append"), vec![self.parse(expr)?, list])
+                }
+                _ => {
+                    let item = self.quasiquote(item, depth)?;
+                    Expr::Procedure(self.primitive("cons"), vec![item, list])
+                }
+            };
+        }
+        Ok(list)
+    }
+
+    /// Name and operand of `(quasiquote x)`, `(unquote x)` and `(unquote-splicing x)`
+    fn quasiquote_keyword<'a>(&self, datum: &'a Expr) -> Option<(&'static str, &'a Expr)> {
+        let Expr::List(items) = datum else {
+            return None;
+        };
+        let [Expr::Symbol(id), operand] = items.as_slice() else {
+            return None;
+        };
+        let keyword = ["quasiquote", "unquote", "unquote-splicing"]
+            .into_iter()
+            .find(|keyword| self.global_name(id) == *keyword)?;
+        Some((keyword, operand))
+    }
+
+    fn has_unquote(&self, template: &Expr, depth: usize) -> bool {
+        match self.quasiquote_keyword(template) {
+            Some(("unquote" | "unquote-splicing", _)) if depth == 0 => true,
+            Some(("unquote" | "unquote-splicing", operand)) => self.has_unquote(operand, depth - 1),
+            Some((_, operand)) => self.has_unquote(operand, depth + 1),
+            None => match template {
+                Expr::List(items) | Expr::Vector(items) => {
+                    items.iter().any(|item| self.has_unquote(item, depth))
+                }
+                _ => false,
+            },
+        }
+    }
+
+    fn ill_formed(&self, datum: &Expr) -> SyntaxError {
+        SyntaxError::IllFormed(write_datum(&self.strip(datum)))
+    }
+
+    /// Builds the expression written by a datum. Operands of calls are kept as data, they are
+    /// parsed when the call is expanded since it may be a macro use
+    pub fn parse(&self, datum: &Expr) -> Result<Expr, SyntaxError> {
+        let Expr::List(items) = datum else {
+            return Ok(datum.clone());
+        };
+        let Some(Expr::Symbol(head)) = items.first() else {
+            return Ok(Expr::List(self.parse_all(items)?));
+        };
+        let ill_formed = || self.ill_formed(datum);
+        let keyword = self.global_name(head);
+        let expr = match (keyword.as_str(), &items[1..]) {
+            ("quote", [quoted]) => Expr::Quote(Box::new(quoted.clone())),
+            ("quasiquote", [template]) => Expr::Quasiquote(Box::new(template.clone())),
+            ("let", [Expr::Symbol(name), bindings, body @ ..]) if !body.is_empty() => {
+                Expr::NamedLetProcedure(
+                    name.clone(),
+                    self.parse_bindings(bindings, datum)?,
+                    self.parse_body(body)?,
+                )
+            }
+            ("let", [bindings, body @ ..]) if !body.is_empty() => Expr::LetProcedure(
+                self.parse_bindings(bindings, datum)?,
+                self.parse_body(body)?,
+            ),
+            ("let*", [bindings, body @ ..]) if !body.is_empty() => Expr::LetStarProcedure(
+                self.parse_bindings(bindings, datum)?,
+                self.parse_body(body)?,
+            ),
+            ("letrec", [bindings, body @ ..]) if !body.is_empty() => Expr::LetrecProcedure(
+                self.parse_bindings(bindings, datum)?,
+                self.parse_body(body)?,
+            ),
+            ("letrec*", [bindings, body @ ..]) if !body.is_empty() => Expr::LetrecStarProcedure(
+                self.parse_bindings(bindings, datum)?,
+                self.parse_body(body)?,
+            ),
+            ("lambda", [formals, body @ ..]) if !body.is_empty() => {
+                let formals = match formals {
+                    Expr::Symbol(rest) => Some(Formals {
+                        args: vec![],
+                        rest: Some(rest.clone()),
+                    }),
+                    Expr::List(formals) => parse_formals(formals),
+                    _ => None,
+                };
+                Expr::Lambda(formals.ok_or_else(ill_formed)?, self.parse_body(body)?)
+            }
+            ("define", [Expr::Symbol(name), value]) => {
+                Expr::DefineExpr(name.clone(), self.parse_boxed(value)?)
+            }
+            ("define", [Expr::List(head), body @ ..]) if !body.is_empty() => {
+                match head.split_first() {
+                    Some((Expr::Symbol(name), formals)) => Expr::DefineProcedure(
+                        name.clone(),
+                        parse_formals(formals).ok_or_else(ill_formed)?,
+                        self.parse_body(body)?,
+                    ),
+                    _ => return Err(ill_formed()),
+                }
+            }
+            ("set!", [Expr::Symbol(name), value]) => {
+                Expr::Set(name.clone(), self.parse_boxed(value)?)
+            }
+            ("do", [Expr::List(variables), Expr::List(exit), body @ ..]) if !exit.is_empty() => {
+                let mut parsed = Vec::new();
+                for variable in variables {
+                    parsed.push(match variable {
+                        Expr::List(parts) => match parts.as_slice() {
+                            [Expr::Symbol(name), init] => (name.clone(), self.parse(init)?, None),
+                            [Expr::Symbol(name), init, step] => {
+                                (name.clone(), self.parse(init)?, Some(self.parse(step)?))
+                            }
+                            _ => return Err(ill_formed()),
+                        },
+                        _ => return Err(ill_formed()),
+                    });
+                }
+                Expr::Do(
+                    parsed,
+                    self.parse_boxed(&exit[0])?,
+                    self.parse_all(&exit[1..])?,
+                    self.parse_all(body)?,
+                )
+            }
+            ("cond", clauses) if !clauses.is_empty() => {
+                let (clauses, else_body) = self.split_else(clauses, datum)?;
+                let mut parsed = Vec::new();
+                for clause in clauses {
+                    parsed.push(match clause {
+                        Expr::List(parts) => match parts.as_slice() {
+                            [test, Expr::Symbol(arrow), receiver]
+                                if self.global_name(arrow) == "=>" =>
+                            {
+                                CondClause::Receiver(self.parse(test)?, self.parse(receiver)?)
+                            }
+                            [test, body @ ..] => {
+                                CondClause::Test(self.parse(test)?, self.parse_all(body)?)
+                            }
+                            [] => return Err(ill_formed()),
+                        },
+                        _ => return Err(ill_formed()),
+                    });
+                }
+                Expr::Cond(parsed, else_body)
+            }
+            ("case", [key, clauses @ ..]) if !clauses.is_empty() => {
+                let (clauses, else_body) = self.split_else(clauses, datum)?;
+                let mut parsed = Vec::new();
+                for clause in clauses {
+                    parsed.push(match clause {
+                        Expr::List(parts) => match parts.as_slice() {
+                            [Expr::List(data), body @ ..] if !body.is_empty() => {
+                                (data.clone(), self.parse_all(body)?)
+                            }
+                            _ => return Err(ill_formed()),
+                        },
+                        _ => return Err(ill_formed()),
+                    });
+                }
+                Expr::Case(self.parse_boxed(key)?, parsed, else_body)
+            }
+            ("and", exprs) => Expr::And(self.parse_all(exprs)?),
+            ("or", exprs) => Expr::Or(self.parse_all(exprs)?),
+            ("when", [test, body @ ..]) if !body.is_empty() => {
+                Expr::When(self.parse_boxed(test)?, self.parse_all(body)?)
+            }
+            ("unless", [test, body @ ..]) if !body.is_empty() => {
+                Expr::Unless(self.parse_boxed(test)?, self.parse_all(body)?)
+            }
+            ("define-syntax", [Expr::Symbol(name), rules]) => {
+                Expr::DefineSyntax(name.clone(), self.parse_syntax_rules(rules, datum)?)
+            }
+            ("let-syntax", [bindings, body @ ..]) if !body.is_empty() => Expr::LetSyntax(
+                self.parse_syntax_bindings(bindings, datum)?,
+                self.parse_body(body)?,
+            ),
+            ("letrec-syntax", [bindings, body @ ..]) if !body.is_empty() => Expr::LetrecSyntax(
+                self.parse_syntax_bindings(bindings, datum)?,
+                self.parse_body(body)?,
+            ),
+            (keyword, _) if SPECIAL_FORMS.contains(&keyword) => return Err(ill_formed()),
+            _ => Expr::Procedure(head.clone(), items[1..].to_vec()),
+        };
+        Ok(expr)
+    }
+
+    fn parse_all(&self, data: &[Expr]) -> Result<Vec<Expr>, SyntaxError> {
+        data.iter().map(|datum| self.parse(datum)).collect()
+    }
+
+    fn parse_boxed(&self, datum: &Expr) -> Result<Box<Expr>, SyntaxError> {
+        self.parse(datum).map(Box::new)
+    }
+
+    fn parse_body(&self, body: &[Expr]) -> Result<Box<Expr>, SyntaxError> {
+        Ok(Box::new(sequence(self.parse_all(body)?)))
+    }
+
+    fn parse_bindings(
+        &self,
+        bindings: &Expr,
+        form: &Expr,
+    ) -> Result<Vec<(String, Expr)>, SyntaxError> {
+        let Expr::List(bindings) = bindings else {
+            return Err(self.ill_formed(form));
+        };
+        bindings
+            .iter()
+            .map(|binding| match binding {
+                Expr::List(parts) => match parts.as_slice() {
+                    [Expr::Symbol(name), init] => Ok((name.clone(), self.parse(init)?)),
+                    _ => Err(self.ill_formed(form)),
+                },
+                _ => Err(self.ill_formed(form)),
+            })
+            .collect()
+    }
+
+    /// Separates the `else` clause, which must be the last one, and parses its body
+    fn split_else<'a>(
+        &self,
+        clauses: &'a [Expr],
+        form: &Expr,
+    ) -> Result<(&'a [Expr], Option<Vec<Expr>>), SyntaxError> {
+        let is_else = |clause: &Expr| match clause {
+            Expr::List(parts) => {
+                matches!(parts.first(), Some(Expr::Symbol(id)) if self.global_name(id) == "else")
+            }
+            _ => false,
+        };
+        match clauses.split_last() {
+            Some((Expr::List(parts), rest)) if is_else(&clauses[clauses.len() - 1]) => {
+                if parts.len() < 2 || rest.iter().any(is_else) {
+                    return Err(self.ill_formed(form));
+                }
+                Ok((rest, Some(self.parse_all(&parts[1..])?)))
+            }
+            _ => Ok((clauses, None)),
+        }
+    }
+
+    fn parse_syntax_bindings(
+        &self,
+        bindings: &Expr,
+        form: &Expr,
+    ) -> Result<Vec<(String, SyntaxRules)>, SyntaxError> {
+        let Expr::List(bindings) = bindings else {
+            return Err(self.ill_formed(form));
+        };
+        bindings
+            .iter()
+            .map(|binding| match binding {
+                Expr::List(parts) => match parts.as_slice() {
+                    [Expr::Symbol(name), rules] => {
+                        Ok((name.clone(), self.parse_syntax_rules(rules, form)?))
+                    }
+                    _ => Err(self.ill_formed(form)),
+                },
+                _ => Err(self.ill_formed(form)),
+            })
+            .collect()
+    }
+
+    fn parse_syntax_rules(&self, rules: &Expr, form: &Expr) -> Result<SyntaxRules, SyntaxError> {
+        let ill_formed = || self.ill_formed(form);
+        let Expr::List(parts) = rules else {
+            return Err(ill_formed());
+        };
+        let (ellipsis, rest) = match parts.as_slice() {
+            [Expr::Symbol(keyword), Expr::Symbol(ellipsis), rest @ ..]
+                if self.global_name(keyword) == "syntax-rules" =>
+            {
+                (ellipsis.clone(), rest)
+            }
+            [Expr::Symbol(keyword), rest @ ..] if self.global_name(keyword) == "syntax-rules" => {
+                ("...".to_owned(), rest)
+            }
+            _ => return Err(ill_formed()),
+        };
+        let Some((Expr::List(literals), rules)) = rest.split_first() else {
+            return Err(ill_formed());
+        };
+        let literals = literals
+            .iter()
+            .map(|literal| match literal {
+                Expr::Symbol(literal) => Ok(literal.clone()),
+                _ => Err(ill_formed()),
+            })
+            .collect::<Result<_, _>>()?;
+        let rules = rules
+            .iter()
+            .map(|rule| match rule {
+                Expr::List(parts) => match parts.as_slice() {
+                    [pattern, template] => Ok((pattern.clone(), template.clone())),
+                    _ => Err(ill_formed()),
+                },
+                _ => Err(ill_formed()),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SyntaxRules {
+            ellipsis,
+            literals,
+            rules,
+        })
+    }
+}
+
+/// Formals of a lambda list, the rest parameter follows a `.`
+fn parse_formals(formals: &[Expr]) -> Option<Formals> {
+    let mut args = Vec::new();
+    let mut rest = None;
+    let mut formals = formals.iter();
+    while let Some(formal) = formals.next() {
+        match formal {
+            Expr::Symbol(dot) if dot == "." => {
+                let Some(Expr::Symbol(name)) = formals.next() else {
+                    return None;
+                };
+                rest = Some(name.clone());
+                if formals.next().is_some() {
+                    return None;
+                }
+            }
+            Expr::Symbol(name) => args.push(name.clone()),
+            _ => return None,
+        }
+    }
+    Some(Formals { args, rest })
+}
+
+/// List of the items ending with the tail of a dotted form, or a proper list without tail
+fn dotted_list(items: &[Expr], tail: Option<&Expr>) -> Expr {
+    match tail {
+        None => Expr::List(items.to_vec()),
+        Some(tail) if items.is_empty() => tail.clone(),
+        Some(tail) => Expr::List(
+            items
+                .iter()
+                .cloned()
+                .chain([symbol("."), tail.clone()])
+                .collect(),
+        ),
+    }
+}
+
+fn template_symbols(template: &Expr, symbols: &mut Vec<String>) {
+    match template {
+        Expr::Symbol(id) if !symbols.contains(id) => symbols.push(id.clone()),
+        Expr::List(items) | Expr::Vector(items) => {
+            for item in items {
+                template_symbols(item, symbols);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn same_atom(lhs: &Expr, rhs: &Expr) -> bool {
+    match (lhs, rhs) {
+        (Expr::Number(lhs), Expr::Number(rhs)) => lhs == rhs,
+        (Expr::Real(lhs), Expr::Real(rhs)) => lhs == rhs,
+        (Expr::Bignum(lhs), Expr::Bignum(rhs)) => lhs == rhs,
+        (Expr::Boolean(lhs), Expr::Boolean(rhs)) => lhs == rhs,
+        (Expr::Char(lhs), Expr::Char(rhs)) => lhs == rhs,
+        (Expr::String(lhs), Expr::String(rhs)) => lhs == rhs,
+        _ => false,
+    }
+}
+
+fn symbol(name: &str) -> Expr {
+    Expr::Symbol(name.to_owned())
+}
+
+fn form(keyword: &str, parts: Vec<Expr>) -> Expr {
+    let mut items = vec![symbol(keyword)];
+    items.extend(parts);
+    Expr::List(items)
+}
+
+fn formals_datum(head: Option<String>, formals: Formals) -> Expr {
+    if head.is_none() && formals.args.is_empty() {
+        if let Some(rest) = formals.rest {
+            return Expr::Symbol(rest);
+        }
+    }
+    let mut items: Vec<Expr> = head
+        .into_iter()
+        .chain(formals.args)
+        .map(Expr::Symbol)
+        .collect();
+    if let Some(rest) = formals.rest {
+        items.extend([symbol("."), Expr::Symbol(rest)]);
+    }
+    Expr::List(items)
+}
+
+fn bindings_datum(bindings: Vec<(String, Expr)>) -> Expr {
+    Expr::List(
+        bindings
+            .into_iter()
+            .map(|(name, init)| Expr::List(vec![Expr::Symbol(name), to_datum(init)]))
+            .collect(),
+    )
+}
+
+fn syntax_rules_datum(rules: SyntaxRules) -> Expr {
+    let mut items = vec![
+        symbol("syntax-rules"),
+        Expr::Symbol(rules.ellipsis),
+        Expr::List(rules.literals.into_iter().map(Expr::Symbol).collect()),
+    ];
+    items.extend(
+        rules
+            .rules
+            .into_iter()
+            .map(|(pattern, template)| Expr::List(vec![pattern, template])),
+    );
+    Expr::List(items)
+}
+
+fn syntax_bindings_datum(bindings: Vec<(String, SyntaxRules)>) -> Expr {
+    Expr::List(
+        bindings
+            .into_iter()
+            .map(|(name, rules)| Expr::List(vec![Expr::Symbol(name), syntax_rules_datum(rules)]))
+            .collect(),
+    )
+}
+
+fn to_data(exprs: Vec<Expr>) -> impl Iterator<Item = Expr> {
+    exprs.into_iter().map(to_datum)
+}
+
+/// Writes an expression back as the datum it was parsed from, so macros can match it
+fn to_datum(expr: Expr) -> Expr {
+    match expr {
+        Expr::Quote(datum) => form("quote", vec![*datum]),
+        Expr::Quasiquote(template) => form("quasiquote", vec![*template]),
+        Expr::Procedure(name, args) => form(&name, to_data(args).collect()),
+        Expr::List(items) => Expr::List(to_data(items).collect()),
+        Expr::LetProcedure(bindings, body) => {
+            form("let", vec![bindings_datum(bindings), to_datum(*body)])
+        }
+        Expr::NamedLetProcedure(name, bindings, body) => form(
+            "let",
+            vec![
+                Expr::Symbol(name),
+                bindings_datum(bindings),
+                to_datum(*body),
+            ],
+        ),
+        Expr::LetStarProcedure(bindings, body) => {
+            form("let*", vec![bindings_datum(bindings), to_datum(*body)])
+        }
+        Expr::LetrecProcedure(bindings, body) => {
+            form("letrec", vec![bindings_datum(bindings), to_datum(*body)])
+        }
+        Expr::LetrecStarProcedure(bindings, body) => {
+            form("letrec*", vec![bindings_datum(bindings), to_datum(*body)])
+        }
+        Expr::Lambda(formals, body) => form(
+            "lambda",
+            vec![formals_datum(None, formals), to_datum(*body)],
+        ),
+        Expr::DefineExpr(name, value) => form("define", vec![Expr::Symbol(name), to_datum(*value)]),
+        Expr::DefineProcedure(name, formals, body) => form(
+            "define",
+            vec![formals_datum(Some(name), formals), to_datum(*body)],
+        ),
+        Expr::Set(name, value) => form("set!", vec![Expr::Symbol(name), to_datum(*value)]),
+        Expr::Do(variables, test, result, body) => {
+            let variables = variables
+                .into_iter()
+                .map(|(name, init, step)| {
+                    let mut parts = vec![Expr::Symbol(name), to_datum(init)];
+                    parts.extend(step.map(to_datum));
+                    Expr::List(parts)
+                })
+                .collect();
+            let exit = std::iter::once(to_datum(*test)).chain(to_data(result));
+            let mut parts = vec![Expr::List(variables), Expr::List(exit.collect())];
+            parts.extend(to_data(body));
+            form("do", parts)
+        }
+        Expr::Cond(clauses, else_body) => {
+            let mut parts: Vec<Expr> = clauses
+                .into_iter()
+                .map(|clause| match clause {
+                    CondClause::Test(test, body) => Expr::List(
+                        std::iter::once(to_datum(test))
+                            .chain(to_data(body))
+                            .collect(),
+                    ),
+                    CondClause::Receiver(test, receiver) => {
+                        Expr::List(vec![to_datum(test), symbol("=>"), to_datum(receiver)])
+                    }
+                })
+                .collect();
+            parts.extend(else_body.map(|body| form("else", to_data(body).collect())));
+            form("cond", parts)
+        }
+        Expr::Case(key, clauses, else_body) => {
+            let mut parts = vec![to_datum(*key)];
+            parts.extend(clauses.into_iter().map(|(data, body)| {
+                Expr::List(
+                    std::iter::once(Expr::List(data))
+                        .chain(to_data(body))
+                        .collect(),
+                )
+            }));
+            parts.extend(else_body.map(|body| form("else", to_data(body).collect())));
+            form("case", parts)
+        }
+        Expr::And(exprs) => form("and", to_data(exprs).collect()),
+        Expr::Or(exprs) => form("or", to_data(exprs).collect()),
+        Expr::When(test, body) => form(
+            "when",
+            std::iter::once(to_datum(*test))
+                .chain(to_data(body))
+                .collect(),
+        ),
+        Expr::Unless(test, body) => form(
+            "unless",
+            std::iter::once(to_datum(*test))
+                .chain(to_data(body))
+                .collect(),
+        ),
+        Expr::DefineSyntax(name, rules) => form(
+            "define-syntax",
+            vec![Expr::Symbol(name), syntax_rules_datum(rules)],
+        ),
+        Expr::LetSyntax(bindings, body) => form(
+            "let-syntax",
+            vec![syntax_bindings_datum(bindings), to_datum(*body)],
+        ),
+        Expr::LetrecSyntax(bindings, body) => form(
+            "letrec-syntax",
+            vec![syntax_bindings_datum(bindings), to_datum(*body)],
+        ),
+        Expr::Number(_)
+        | Expr::Real(_)
+        | Expr::Bignum(_)
+        | Expr::Boolean(_)
+        | Expr::Char(_)
+        | Expr::Symbol(_)
+        | Expr::String(_)
+        | Expr::Vector(_)
+        | Expr::Error => expr,
+    }
+}
+
+fn write_data(items: &[Expr]) -> String {
+    items.iter().map(write_datum).collect::<Vec<_>>().join(" ")
+}
+
+fn write_datum(datum: &Expr) -> String {
+    match datum {
+        Expr::List(items) => format!("({})", write_data(items)),
+        Expr::Vector(items) => format!("#({})", write_data(items)),
+        Expr::Symbol(id) => id.clone(),
+        Expr::Number(value) => value.to_string(),
+        Expr::Real(value) => value.to_string(),
+        Expr::Bignum(digits) => digits.clone(),
+        Expr::Boolean(true) => "#t".to_owned(),
+        Expr::Boolean(false) => "#f".to_owned(),
+        Expr::Char(value) => format!("#\\{value}"),
+        Expr::String(value) => format!("{value:?}"),
+        _ => format!("{datum:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str) -> Expr {
+        Expr::Symbol(name.to_owned())
+    }
+
+    fn list(items: Vec<Expr>) -> Expr {
+        Expr::List(items)
+    }
+
+    fn call(name: &str, args: Vec<Expr>) -> Expr {
+        Expr::Procedure(name.to_owned(), args)
+    }
+
+    fn define_syntax(name: &str, literals: &[&str], rules: Vec<(Expr, Expr)>) -> Expr {
+        let literals = literals.iter().map(|literal| literal.to_string()).collect();
+        let rules = SyntaxRules {
+            ellipsis: "...".to_owned(),
+            literals,
+            rules,
+        };
+        Expr::DefineSyntax(name.to_owned(), rules)
+    }
+
+    #[test]
+    fn expands_ellipsis() {
+        let my_list = define_syntax(
+            "my-list",
+            &[],
+            vec![(
+                list(vec![symbol("_"), symbol("x"), symbol("...")]),
+                list(vec![symbol("list"), symbol("x"), symbol("...")]),
+            )],
+        );
+        let use_expr = call("my-list", vec![Expr::Number(1), Expr::Number(2)]);
+        let expanded = Expander::default()
+            .expand_module(vec![my_list, use_expr])
+            .unwrap();
+        assert_eq!(expanded.len(), 1);
+        let Expr::Procedure(name, args) = &expanded[0] else {
+            panic!("Expected call, got {expanded:?}");
+        };
+        assert_eq!(name, "list");
+        assert!(matches!(
+            args.as_slice(),
+            [Expr::Number(1), Expr::Number(2)]
+        ));
+    }
+
+    #[test]
+    fn introduced_bindings_do_not_capture() {
+        // (define-syntax my-or2 (syntax-rules () ((_ a b) (let ((t a)) (if t t b)))))
+        let my_or = define_syntax(
+            "my-or2",
+            &[],
+            vec![(
+                list(vec![symbol("_"), symbol("a"), symbol("b")]),
+                list(vec![
+                    symbol("let"),
+                    list(vec![list(vec![symbol("t"), symbol("a")])]),
+                    list(vec![symbol("if"), symbol("t"), symbol("t"), symbol("b")]),
+                ]),
+            )],
+        );
+        let use_expr = call("my-or2", vec![Expr::Boolean(false), symbol("t")]);
+        let expanded = Expander::default()
+            .expand_module(vec![my_or, use_expr])
+            .unwrap();
+        let Expr::LetProcedure(bindings, body) = &expanded[0] else {
+            panic!("Expected let, got {expanded:?}");
+        };
+        let temp = &bindings[0].0;
+        assert_ne!(temp, "t");
+        let Expr::Procedure(name, args) = body.as_ref() else {
+            panic!("Expected if, got {body:?}");
+        };
+        assert_eq!(name, "if");
+        assert!(matches!(&args[0], Expr::Symbol(test) if test == temp));
+        assert!(matches!(&args[2], Expr::Symbol(user) if user == "t"));
+    }
+
+    #[test]
+    fn expands_quasiquote() {
+        // `(a ,x ,@y)
+        let template = list(vec![
+            symbol("a"),
+            list(vec![symbol("unquote"), symbol("x")]),
+            list(vec![symbol("unquote-splicing"), symbol("y")]),
+        ]);
+        let expanded = Expander::default()
+            .expand_module(vec![Expr::Quasiquote(Box::new(template))])
+            .unwrap();
+        let Expr::Procedure(name, args) = &expanded[0] else {
+            panic!("Expected cons, got {expanded:?}");
+        };
+        assert_eq!(name, "cons");
+        assert!(
+            matches!(&args[0], Expr::Quote(datum) if matches!(datum.as_ref(), Expr::Symbol(a) if a == "a"))
+        );
+        let Expr::Procedure(name, args) = &args[1] else {
+            panic!("Expected cons, got {args:?}");
+        };
+        assert_eq!(name, "cons");
+        assert!(matches!(&args[0], Expr::Symbol(x) if x == "x"));
+        assert!(matches!(&args[1], Expr::Procedure(append, _) if append == "append"));
+    }
+
+    #[test]
+    fn binds_internal_definitions() {
+        // (lambda () (define a 1) (define (f) a) (f))
+        let body = sequence(vec![
+            Expr::DefineExpr("a".to_owned(), Box::new(Expr::Number(1))),
+            Expr::DefineProcedure("f".to_owned(), Formals::default(), Box::new(symbol("a"))),
+            call("f", vec![]),
+        ]);
+        let lambda = Expr::Lambda(Formals::default(), Box::new(body));
+        let expanded = Expander::default().expand_module(vec![lambda]).unwrap();
+        let Expr::Lambda(_, body) = &expanded[0] else {
+            panic!("Expected lambda, got {expanded:?}");
+        };
+        let Expr::LetrecStarProcedure(bindings, body) = body.as_ref() else {
+            panic!("Expected letrec*, got {body:?}");
+        };
+        let names = bindings.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert!(
+            matches!(&bindings[1].1, Expr::Lambda(_, a) if matches!(a.as_ref(), Expr::Symbol(a) if a == names[0]))
+        );
+        assert!(matches!(body.as_ref(), Expr::Procedure(f, _) if f == names[1]));
+    }
+
+    #[test]
+    fn rejects_definitions_after_expressions() {
+        let body = sequence(vec![
+            call("display", vec![Expr::Number(1)]),
+            Expr::DefineExpr("a".to_owned(), Box::new(Expr::Number(1))),
+        ]);
+        let lambda = Expr::Lambda(Formals::default(), Box::new(body));
+        let result = Expander::default().expand_module(vec![lambda]);
+        assert_eq!(
+            result.unwrap_err(),
+            SyntaxError::MisplacedDefinition("a".to_owned())
+        );
+    }
+
+    #[test]
+    fn splices_toplevel_begin() {
+        let define = Expr::DefineExpr("a".to_owned(), Box::new(Expr::Number(1)));
+        let begin = call("begin", vec![define, call("display", vec![symbol("a")])]);
+        let expanded = Expander::default().expand_module(vec![begin]).unwrap();
+        assert!(matches!(
+            expanded.as_slice(),
+            [Expr::DefineExpr(..), Expr::Procedure(display, _)] if display == "display"
+        ));
+    }
+
+    #[test]
+    fn reports_uses_without_matching_rule() {
+        let my_if = define_syntax(
+            "my-if",
+            &["then"],
+            vec![(
+                list(vec![symbol("_"), symbol("c"), symbol("then"), symbol("a")]),
+                list(vec![symbol("if"), symbol("c"), symbol("a")]),
+            )],
+        );
+        let use_expr = call(
+            "my-if",
+            vec![Expr::Boolean(true), symbol("else"), symbol("x")],
+        );
+        let result = Expander::default().expand_module(vec![my_if.clone(), use_expr]);
+        assert_eq!(
+            result.unwrap_err(),
+            SyntaxError::IllFormed("(my-if #t else x)".to_owned())
+        );
+        let result = Expander::default().expand_module(vec![my_if, symbol("my-if")]);
+        assert_eq!(
+            result.unwrap_err(),
+            SyntaxError::KeywordAsExpression("my-if".to_owned())
+        );
+    }
+}
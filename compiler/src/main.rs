@@ -4,12 +4,14 @@ extern crate lalrpop_util;
 use clap::Parser;
 use compilisp::ast::ModuleAst;
 use compilisp::backend::llvm_context::Context;
+use compilisp::comments::strip_comments;
+use compilisp::macros::Expander;
 use lalrpop_util::ParseError;
 use std::fs::File;
 use std::io;
 use std::io::Read;
 
-lalrpop_mod!(pub lisp); // synthesized by LALRPOP
+lalrpop_mod!(#[allow(clippy::all)] pub lisp); // synthesized by LALRPOP
 
 #[derive(Parser)]
 struct CliArgs {
@@ -28,6 +30,7 @@ fn compile(args: CliArgs) -> io::Result<()> {
     let mut module_file = File::open(&args.input)?;
     let mut module_text = String::new();
     module_file.read_to_string(&mut module_text)?;
+    let module_text = strip_comments(&module_text);
 
     let mut errors = Vec::new();
     let parser = lisp::ModuleParser::new();
@@ -49,10 +52,17 @@ fn compile(args: CliArgs) -> io::Result<()> {
                     }
                 }
             } else {
-                let compiler = Context::new();
-                let source = args.input.to_string_lossy().to_string();
-                let root = ModuleAst { expr_vec, source };
-                compiler.add_module(root);
+                match Expander::default().expand_module(expr_vec) {
+                    Ok(expr_vec) => {
+                        let compiler = Context::new();
+                        let source = args.input.to_string_lossy().to_string();
+                        let root = ModuleAst { expr_vec, source };
+                        if let Err(error) = compiler.add_module(root) {
+                            println!("Failed to compile: {error}");
+                        }
+                    }
+                    Err(error) => println!("Failed to compile: {error}"),
+                }
             }
         }
         Err(e) => {
@@ -62,10 +72,21 @@ fn compile(args: CliArgs) -> io::Result<()> {
     Ok(())
 }
 
+/// Reads an expression and parses the datum like the expander does
+#[cfg(test)]
+fn parse_expression(source: &str) -> Result<compilisp::ast::Expr, String> {
+    let datum = lisp::ExpressionParser::new()
+        .parse(&mut Vec::new(), source)
+        .map_err(|error| error.to_string())?;
+    Expander::default()
+        .parse(&datum)
+        .map_err(|error| error.to_string())
+}
+
 #[test]
 fn parse_sum() {
-    let parser = lisp::ExpressionParser::new();
-    let ast = parser.parse("(sum 2 3)");
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(sum 2 3)");
     if let Ok(Expr::Procedure(sum, values)) = ast {
         assert_eq!(sum, "sum");
         assert_eq!(values.len(), 2);
@@ -74,8 +95,265 @@ fn parse_sum() {
 
 #[test]
 fn parse_let() {
-    let parser = lisp::ExpressionParser::new();
-    let ast = parser.parse("(let ((x 2)) (+ 3 x))");
+    let ast = parse_expression("(let ((x 2)) (+ 3 x))");
     println!("ast: {:?}", ast);
     assert!(ast.is_ok());
 }
+
+#[test]
+fn parse_named_let() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(let loop ((i 0)) (loop (+ i 1)))");
+    if let Ok(Expr::NamedLetProcedure(name, bindings, _)) = ast {
+        assert_eq!(name, "loop");
+        assert_eq!(bindings.len(), 1);
+    } else {
+        panic!("Expected named let, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_do() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(do ((i 0 (+ i 1)) (j 0)) ((> i 5) j) (display i))");
+    if let Ok(Expr::Do(variables, _, result, body)) = ast {
+        assert_eq!(variables.len(), 2);
+        assert!(variables[1].2.is_none());
+        assert_eq!(result.len(), 1);
+        assert_eq!(body.len(), 1);
+    } else {
+        panic!("Expected do loop, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_letrec() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(letrec ((f (lambda (n) (g n))) (g (lambda (n) n))) (f 1))");
+    if let Ok(Expr::LetrecProcedure(bindings, _)) = ast {
+        assert_eq!(bindings.len(), 2);
+        assert!(matches!(bindings[0].1, Expr::Lambda(..)));
+    } else {
+        panic!("Expected letrec, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_lambda_call() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("((lambda (x y) (+ x y)) 1 2)");
+    if let Ok(Expr::List(items)) = ast {
+        assert_eq!(items.len(), 3);
+        assert!(matches!(items[0], Expr::Lambda(..)));
+    } else {
+        panic!("Expected lambda call, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_set() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(set! x (+ x 1))");
+    if let Ok(Expr::Set(name, value)) = ast {
+        assert_eq!(name, "x");
+        assert!(matches!(*value, Expr::Procedure(..)));
+    } else {
+        panic!("Expected set!, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_quote() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("'foo");
+    assert!(matches!(ast, Ok(Expr::Quote(symbol)) if matches!(*symbol, Expr::Symbol(_))));
+    let ast = parse_expression("(quote (let 1 'x))");
+    assert!(
+        matches!(ast, Ok(Expr::Quote(list)) if matches!(*list, Expr::List(ref items) if items.len() == 3))
+    );
+    let ast = parse_expression("'(1 (2 . 3))");
+    let Ok(Expr::Quote(list)) = ast else {
+        panic!("Expected quotation, got {ast:?}");
+    };
+    assert!(
+        matches!(&*list, Expr::List(items) if matches!(&items[1], Expr::List(pair) if pair.len() == 3))
+    );
+}
+
+#[test]
+fn parse_rest_parameters() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(define (f a b . rest) rest)");
+    let Ok(Expr::DefineProcedure(_, define_args, _)) = ast else {
+        panic!("Expected define, got {ast:?}");
+    };
+    assert_eq!(define_args.args, vec!["a", "b"]);
+    assert_eq!(define_args.rest.as_deref(), Some("rest"));
+    let ast = parse_expression("(lambda args args)");
+    let Ok(Expr::Lambda(lambda_args, _)) = ast else {
+        panic!("Expected lambda, got {ast:?}");
+    };
+    assert!(lambda_args.args.is_empty());
+    assert_eq!(lambda_args.rest.as_deref(), Some("args"));
+    // Operands are kept as data, the dot is parsed when the call is expanded
+    let ast = parse_expression("(f (lambda (x . more) more))");
+    let Ok(Expr::Procedure(_, args)) = ast else {
+        panic!("Expected call, got {ast:?}");
+    };
+    let Expr::List(lambda) = &args[0] else {
+        panic!("Expected lambda datum, got {:?}", args[0]);
+    };
+    assert!(matches!(&lambda[1], Expr::List(formals) if formals.len() == 3));
+}
+
+#[test]
+fn parse_booleans() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(f #t #f #true #false)");
+    if let Ok(Expr::Procedure(_, args)) = ast {
+        let values = args
+            .iter()
+            .map(|arg| match arg {
+                Expr::Boolean(value) => *value,
+                _ => panic!("Expected boolean, got {arg:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![true, false, true, false]);
+    } else {
+        panic!("Expected call, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_chars() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression(r"(f #\a #\space #\newline #\x41 #\()");
+    if let Ok(Expr::Procedure(_, args)) = ast {
+        let values = args
+            .iter()
+            .map(|arg| match arg {
+                Expr::Char(value) => *value,
+                _ => panic!("Expected char, got {arg:?}"),
+            })
+            .collect::<String>();
+        assert_eq!(values, "a \nA(");
+    } else {
+        panic!("Expected call, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_numbers() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(- -5 2.75 .5 1e3 +2 +12345678901)");
+    if let Ok(Expr::Procedure(name, args)) = ast {
+        assert_eq!(name, "-");
+        assert!(matches!(args[0], Expr::Number(-5)));
+        assert!(matches!(args[1], Expr::Real(value) if value == 2.75));
+        assert!(matches!(args[2], Expr::Real(value) if value == 0.5));
+        assert!(matches!(args[3], Expr::Real(value) if value == 1000.0));
+        assert!(matches!(args[4], Expr::Number(2)));
+        assert!(matches!(&args[5], Expr::Bignum(digits) if digits == "12345678901"));
+    } else {
+        panic!("Expected call, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_string_escapes() {
+    use compilisp::ast::Expr;
+    let mut errors = Vec::new();
+    let parser = lisp::ExpressionParser::new();
+    let source = r#""a\"b\\\t\x41;\
+        c""#;
+    let ast = parser.parse(&mut errors, source);
+    if let Ok(Expr::String(value)) = ast {
+        assert_eq!(value, "a\"b\\\tAc");
+    } else {
+        panic!("Expected string, got {ast:?}");
+    }
+    assert!(parser.parse(&mut errors, r#""\q""#).is_err());
+    assert!(parser.parse(&mut errors, r#""bad\x41""#).is_err());
+}
+
+#[test]
+fn parse_bodies() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(let ((x 1)) x)");
+    assert!(matches!(ast, Ok(Expr::LetProcedure(_, body)) if matches!(*body, Expr::Symbol(_))));
+    let ast = parse_expression("(lambda (x) (define y x) (display y) y)");
+    if let Ok(Expr::Lambda(_, body)) = ast {
+        assert!(
+            matches!(*body, Expr::Procedure(name, exprs) if name == "begin" && exprs.len() == 3)
+        );
+    } else {
+        panic!("Expected lambda, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_quasiquote() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("`(a ,b ,@c)");
+    if let Ok(Expr::Quasiquote(template)) = ast {
+        let Expr::List(items) = *template else {
+            panic!("Expected list template, got {template:?}");
+        };
+        assert!(
+            matches!(&items[1], Expr::List(unquote) if matches!(&unquote[0], Expr::Symbol(name) if name == "unquote"))
+        );
+        assert!(
+            matches!(&items[2], Expr::List(splice) if matches!(&splice[0], Expr::Symbol(name) if name == "unquote-splicing"))
+        );
+    } else {
+        panic!("Expected quasiquote, got {ast:?}");
+    }
+}
+
+#[test]
+fn parse_define_syntax() {
+    use compilisp::ast::Expr;
+    let source = "(define-syntax my-if (syntax-rules (then else) ((_ c then a else b) (cond (c a) (else b)))))";
+    let ast = parse_expression(source);
+    if let Ok(Expr::DefineSyntax(name, rules)) = ast {
+        assert_eq!(name, "my-if");
+        assert_eq!(rules.ellipsis, "...");
+        assert_eq!(rules.literals, vec!["then", "else"]);
+        assert_eq!(rules.rules.len(), 1);
+    } else {
+        panic!("Expected syntax definition, got {ast:?}");
+    }
+    let ast = parse_expression("(my-if #t then 1 else 2)");
+    assert!(matches!(ast, Ok(Expr::Procedure(_, args)) if args.len() == 5));
+    let ast = parse_expression("(my-cond (#t 1) (else 2))");
+    assert!(
+        matches!(ast, Ok(Expr::Procedure(_, args)) if matches!(&args[..], [Expr::List(_), Expr::List(_)]))
+    );
+}
+
+#[test]
+fn parse_cond() {
+    use compilisp::ast::{CondClause, Expr};
+    let ast = parse_expression("(cond ((< x 0) 1) ((f x) => g) (else 2))");
+    if let Ok(Expr::Cond(clauses, Some(else_body))) = ast {
+        assert_eq!(clauses.len(), 2);
+        assert!(matches!(clauses[1], CondClause::Receiver(..)));
+        assert_eq!(else_body.len(), 1);
+    } else {
+        panic!("Expected cond, got {ast:?}");
+    }
+    assert!(parse_expression("(cond)").is_err());
+}
+
+#[test]
+fn parse_case() {
+    use compilisp::ast::Expr;
+    let ast = parse_expression("(case (+ x 1) ((1 2) 3) ((4) 5))");
+    if let Ok(Expr::Case(_, clauses, None)) = ast {
+        assert_eq!(clauses.len(), 2);
+        assert_eq!(clauses[0].0.len(), 2);
+    } else {
+        panic!("Expected case, got {ast:?}");
+    }
+    assert!(parse_expression("(case 1)").is_err());
+}
@@ -1,9 +1,20 @@
-use crate::runtime::{CompilispObject, CompilispRuntime, CompilispValue};
-use std::ffi::{c_char, CStr};
+use crate::runtime::{
+    CompilispClosure, CompilispError, CompilispObject, CompilispRuntime, CompilispValue,
+};
+use num_bigint::BigInt;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr};
 use std::io;
 use std::io::Write;
 use std::slice::from_raw_parts;
 
+/// Reports a scheme error and aborts the program, panics can't unwind through compiled code
+fn raise(error: CompilispError) -> ! {
+    io::stdout().flush().ok();
+    eprintln!(";{error}");
+    std::process::exit(14)
+}
+
 #[no_mangle]
 pub extern "C" fn compilisp_init() -> *mut CompilispRuntime {
     let b = Box::<CompilispRuntime>::default();
@@ -34,6 +45,102 @@ pub unsafe extern "C" fn compilisp_procedure_call(
         .flat_map(CompilispValue::try_from)
         .collect::<Vec<_>>();
     let procedure_name = name.to_str().unwrap();
-    let result = CompilispRuntime::procedure_call(procedure_name, args.as_slice()).unwrap();
+    let result = CompilispRuntime::procedure_call(procedure_name, args.as_slice())
+        .unwrap_or_else(|error| raise(error));
     CompilispObject::from(&result)
 }
+
+#[no_mangle]
+/// # Safety
+/// function must be a compiled procedure
+/// env should be an array of CompilispObject with size = env_size
+pub unsafe extern "C" fn compilisp_make_closure(
+    function: *const c_void,
+    env: *const CompilispObject,
+    env_size: u32,
+) -> CompilispObject {
+    let env = from_raw_parts(env, env_size as usize).to_vec();
+    let closure = CompilispClosure {
+        function,
+        env: Box::leak(env.into_boxed_slice()).as_mut_ptr(),
+        env_size,
+    };
+    let closure = Box::into_raw(Box::new(closure));
+    CompilispObject::from(&CompilispValue::Procedure(closure))
+}
+
+#[no_mangle]
+/// # Safety
+/// values should be an array of CompilispObject with size = size
+pub unsafe extern "C" fn compilisp_make_list(
+    values: *const CompilispObject,
+    size: u32,
+) -> CompilispObject {
+    let objects = from_raw_parts(values, size as usize);
+    let values = objects
+        .iter()
+        .flat_map(CompilispValue::try_from)
+        .collect::<Vec<_>>();
+    let list =
+        CompilispRuntime::procedure_call("list", &values).unwrap_or_else(|error| raise(error));
+    CompilispObject::from(&list)
+}
+
+#[no_mangle]
+/// Called by compiled procedures receiving a wrong number of arguments
+pub extern "C" fn compilisp_arity_error(argc: u32, required: u32, variadic: u32) -> ! {
+    raise(CompilispError::WrongArity(argc, required, variadic != 0))
+}
+
+#[no_mangle]
+/// # Safety
+/// name must be a valid C string
+pub unsafe extern "C" fn compilisp_intern_symbol(name: *const c_char) -> CompilispObject {
+    let name = CStr::from_ptr(name).to_str().unwrap();
+    CompilispObject::from(&CompilispValue::Symbol(name.to_owned()))
+}
+
+#[no_mangle]
+/// # Safety
+/// digits must be a valid C string with a decimal integer
+pub unsafe extern "C" fn compilisp_make_bignum(digits: *const c_char) -> CompilispObject {
+    let digits = CStr::from_ptr(digits).to_str().unwrap();
+    let value = digits
+        .parse::<BigInt>()
+        .expect("Integer literals are decimal");
+    CompilispObject::from(&CompilispValue::Bignum(value))
+}
+
+#[no_mangle]
+/// # Safety
+/// value must be a valid pointer to a CompilispObject
+pub unsafe extern "C" fn compilisp_make_box(value: *const CompilispObject) -> CompilispObject {
+    CompilispObject::new_box(*value)
+}
+
+#[no_mangle]
+/// # Safety
+/// object must be a valid pointer to a CompilispObject
+pub unsafe extern "C" fn compilisp_get_closure(
+    object: *const CompilispObject,
+) -> *const CompilispClosure {
+    match CompilispValue::try_from(&*object) {
+        Ok(CompilispValue::Procedure(closure)) => closure,
+        _ => raise(CompilispError::NotAProcedure),
+    }
+}
+
+thread_local! {
+    static TAIL_CALL_BUFFER: RefCell<Vec<CompilispObject>> = const { RefCell::new(Vec::new()) };
+}
+
+#[no_mangle]
+/// Array where tail calls pass their arguments, since the caller frame is released on the call.
+/// Compiled procedures copy their arguments on entry, so the buffer can be reused by the next call
+pub extern "C" fn compilisp_tail_call_buffer(size: u32) -> *mut CompilispObject {
+    TAIL_CALL_BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        buffer.reserve(size as usize);
+        buffer.as_mut_ptr()
+    })
+}
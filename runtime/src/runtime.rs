@@ -1,5 +1,11 @@
-use std::ffi::{c_char, CStr};
-use std::fmt::Debug;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt::{Debug, Display, Formatter};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -8,28 +14,194 @@ pub enum CompilispType {
     Boolean,
     String,
     Symbol,
+    Procedure,
+    Box,
+    Pair,
+    Null,
+    Vector,
+    Unspecified,
+    Char,
+    Real,
+    Bignum,
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub union CompilispObjectValue {
     int_value: i32,
     bool_value: bool,
     str_value: *mut c_char,
+    closure_value: *mut CompilispClosure,
+    box_value: *mut CompilispObject,
+    pair_value: *mut CompilispPair,
+    vector_value: *mut Vec<CompilispObject>,
+    char_value: u32,
+    real_value: f64,
+    bignum_value: *mut BigInt,
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct CompilispObject {
     type_: CompilispType,
     value: CompilispObjectValue,
 }
 
+impl CompilispObject {
+    /// Copies the object into the heap, so it can be shared and assigned by many procedures
+    pub fn new_box(value: CompilispObject) -> Self {
+        let value = CompilispObjectValue {
+            box_value: Box::into_raw(Box::new(value)),
+        };
+        Self {
+            type_: CompilispType::Box,
+            value,
+        }
+    }
+}
+
+thread_local! {
+    static SYMBOL_TABLE: RefCell<HashMap<String, *mut c_char>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the unique name of the symbol, so symbols with the same name are the same object
+fn intern_symbol(name: &str) -> *mut c_char {
+    SYMBOL_TABLE.with(|table| {
+        *table
+            .borrow_mut()
+            .entry(name.to_owned())
+            .or_insert_with(|| CString::new(name).unwrap().into_raw())
+    })
+}
+
+/// Compiled procedure with the values it captured.
+/// `function` receives `(argc, argv, env)`, where `env` are the captured values
+#[repr(C)]
+#[derive(Debug)]
+pub struct CompilispClosure {
+    pub function: *const c_void,
+    pub env: *mut CompilispObject,
+    pub env_size: u32,
+}
+
+/// Heap allocated pair, lists are chains of pairs ending with the empty list
+#[repr(C)]
+pub struct CompilispPair {
+    pub car: CompilispObject,
+    pub cdr: CompilispObject,
+}
+
+fn new_pair(car: &CompilispValue, cdr: &CompilispValue) -> CompilispValue {
+    let pair = CompilispPair {
+        car: CompilispObject::from(car),
+        cdr: CompilispObject::from(cdr),
+    };
+    CompilispValue::Pair(Box::into_raw(Box::new(pair)))
+}
+
+fn pair_values(pair: *mut CompilispPair) -> (CompilispValue, CompilispValue) {
+    let pair = unsafe { &*pair };
+    let car = CompilispValue::try_from(&pair.car).expect("Pairs hold scheme values");
+    let cdr = CompilispValue::try_from(&pair.cdr).expect("Pairs hold scheme values");
+    (car, cdr)
+}
+
+/// Items of a proper list, `None` for other values
+fn list_values(list: &CompilispValue) -> Option<Vec<CompilispValue>> {
+    let mut values = Vec::new();
+    let mut list = list.clone();
+    loop {
+        match list {
+            CompilispValue::Null => return Some(values),
+            CompilispValue::Pair(pair) => {
+                let (car, cdr) = pair_values(pair);
+                values.push(car);
+                list = cdr;
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn new_vector(values: &[CompilispValue]) -> CompilispValue {
+    let vector = values.iter().map(CompilispObject::from).collect::<Vec<_>>();
+    CompilispValue::Vector(Box::into_raw(Box::new(vector)))
+}
+
+fn vector_values(vector: *mut Vec<CompilispObject>) -> Vec<CompilispValue> {
+    let vector = unsafe { &*vector };
+    vector
+        .iter()
+        .map(|object| CompilispValue::try_from(object).expect("Vectors hold scheme values"))
+        .collect()
+}
+
+/// Checks the index is in the vector, compiled code can't recover from a panic
+fn vector_slot(
+    vector: *mut Vec<CompilispObject>,
+    index: i32,
+) -> CompilispResult<&'static mut CompilispObject> {
+    let vector = unsafe { &mut *vector };
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| vector.get_mut(index))
+        .ok_or(CompilispError::IndexOutOfRange(index))
+}
+
 #[derive(Debug)]
 pub enum CompilispError {
     UnboundVariable(String),
     ArgTypeMismatch,
+    NotAProcedure,
+    IndexOutOfRange(i32),
+    /// Arguments received, required arguments and whether more are accepted
+    WrongArity(u32, u32, bool),
+    /// Written object, index of the argument and the procedure it was passed to
+    WrongType(String, usize, String),
+    /// Procedure dividing by zero
+    DivisionByZero(String),
+}
+
+const ORDINALS: &[&str] = &[
+    "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth", "tenth",
+];
+
+impl Display for CompilispError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompilispError::UnboundVariable(name) => write!(f, "Unbound variable: {name}"),
+            CompilispError::ArgTypeMismatch => write!(f, "Wrong type argument"),
+            CompilispError::NotAProcedure => write!(f, "The object is not applicable"),
+            CompilispError::IndexOutOfRange(index) => {
+                write!(f, "Index {index} is not in the correct range")
+            }
+            CompilispError::WrongArity(given, required, variadic) => {
+                let bound = if *variadic { "at least" } else { "exactly" };
+                write!(
+                    f,
+                    "The procedure has been called with {given} arguments; \
+                    it requires {bound} {required} arguments"
+                )
+            }
+            CompilispError::WrongType(object, index, procedure) => {
+                let ordinal = match ORDINALS.get(*index) {
+                    Some(ordinal) => ordinal.to_string(),
+                    None => format!("{}th", index + 1),
+                };
+                write!(
+                    f,
+                    "The object {object}, passed as the {ordinal} argument to {procedure}, \
+                    is not the correct type."
+                )
+            }
+            CompilispError::DivisionByZero(procedure) => {
+                write!(f, "Division by zero signalled by {procedure}.")
+            }
+        }
+    }
 }
 
-type CompilispResult<T> = Result<T, CompilispError>;
+pub type CompilispResult<T> = Result<T, CompilispError>;
 
 #[derive(Clone, Debug)]
 pub enum CompilispValue {
@@ -37,6 +209,176 @@ pub enum CompilispValue {
     Boolean(bool),
     String(String),
     Symbol(String),
+    Procedure(*mut CompilispClosure),
+    Pair(*mut CompilispPair),
+    Null,
+    Vector(*mut Vec<CompilispObject>),
+    /// Result of expressions whose value isn't specified by scheme
+    Unspecified,
+    Char(char),
+    /// Inexact number
+    Real(f64),
+    /// Exact integer too large for a fixnum
+    Bignum(BigInt),
+}
+
+/// Names of the characters written with `#\name`
+const CHAR_NAMES: &[(char, &str)] = &[
+    (' ', "space"),
+    ('\n', "newline"),
+    ('\t', "tab"),
+    ('\r', "return"),
+    ('\0', "null"),
+    ('\x07', "alarm"),
+    ('\x08', "backspace"),
+    ('\x1b', "altmode"),
+    ('\x7f', "delete"),
+];
+
+/// Representation of a value read back by `read`, as printed by `write`
+pub struct Written<'a>(pub &'a CompilispValue);
+
+impl Display for Written<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt_value(f, true)
+    }
+}
+
+impl Display for CompilispValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_value(f, false)
+    }
+}
+
+/// Writes a string as a literal, with the escapes of the Scheme syntax. Control characters
+/// without a mnemonic escape are written as hex escapes, like `\x1b;`
+fn fmt_string_literal(f: &mut Formatter<'_>, value: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\t' => write!(f, "\\t")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\x07' => write!(f, "\\a")?,
+            c if c.is_control() => write!(f, "\\x{:x};", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Prints reals like MIT scheme, integral reals end with a dot and the leading zero of
+/// fractions is omitted, like `1.` and `.5`
+fn fmt_real(f: &mut Formatter<'_>, value: f64) -> std::fmt::Result {
+    if value.is_nan() {
+        return write!(f, "+nan.0");
+    }
+    if value.is_infinite() {
+        let sign = if value > 0.0 { "+" } else { "-" };
+        return write!(f, "{sign}inf.0");
+    }
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    // Shortest digits that read back as the same value
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent = exponent.parse::<i32>().unwrap();
+    let digits = mantissa.replace('.', "");
+    if !(-7..21).contains(&exponent) {
+        return write!(f, "{sign}{mantissa}e{exponent}");
+    }
+    if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        return write!(f, "{sign}.{zeros}{digits}");
+    }
+    let integral = exponent as usize + 1;
+    if digits.len() <= integral {
+        let zeros = "0".repeat(integral - digits.len());
+        write!(f, "{sign}{digits}{zeros}.")
+    } else {
+        let (integral, fraction) = digits.split_at(integral);
+        write!(f, "{sign}{integral}.{fraction}")
+    }
+}
+
+impl CompilispValue {
+    fn is_number(&self) -> bool {
+        matches!(
+            self,
+            CompilispValue::Number(_) | CompilispValue::Bignum(_) | CompilispValue::Real(_)
+        )
+    }
+
+    /// Integral inexact numbers, like `2.`, are integers too
+    fn is_integer(&self) -> bool {
+        match self {
+            CompilispValue::Number(_) | CompilispValue::Bignum(_) => true,
+            CompilispValue::Real(value) => value.fract() == 0.0,
+            _ => false,
+        }
+    }
+
+    /// Written values print strings and characters as literals, items of lists and vectors
+    /// are printed the same way as the containing value
+    fn fmt_value(&self, f: &mut Formatter<'_>, written: bool) -> std::fmt::Result {
+        let item = |value: &CompilispValue| -> String {
+            if written {
+                Written(value).to_string()
+            } else {
+                value.to_string()
+            }
+        };
+        match self {
+            CompilispValue::Number(num) => write!(f, "{num}"),
+            CompilispValue::Real(value) => fmt_real(f, *value),
+            CompilispValue::Bignum(value) => write!(f, "{value}"),
+            CompilispValue::Boolean(true) => write!(f, "#t"),
+            CompilispValue::Boolean(false) => write!(f, "#f"),
+            CompilispValue::String(value) if written => fmt_string_literal(f, value),
+            CompilispValue::String(value) => write!(f, "{value}"),
+            CompilispValue::Char(value) if written => {
+                match CHAR_NAMES.iter().find(|(named, _)| named == value) {
+                    Some((_, name)) => write!(f, "#\\{name}"),
+                    None => write!(f, "#\\{value}"),
+                }
+            }
+            CompilispValue::Char(value) => write!(f, "{value}"),
+            CompilispValue::Symbol(name) => write!(f, "{name}"),
+            CompilispValue::Procedure(_) => write!(f, "#[compiled-procedure]"),
+            CompilispValue::Null => write!(f, "()"),
+            CompilispValue::Unspecified => Ok(()),
+            CompilispValue::Vector(vector) => {
+                write!(f, "#(")?;
+                for (i, value) in vector_values(*vector).iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item(value))?;
+                }
+                write!(f, ")")
+            }
+            CompilispValue::Pair(pair) => {
+                let (mut car, mut cdr) = pair_values(*pair);
+                write!(f, "({}", item(&car))?;
+                loop {
+                    match cdr {
+                        CompilispValue::Pair(next) => {
+                            (car, cdr) = pair_values(next);
+                            write!(f, " {}", item(&car))?;
+                        }
+                        CompilispValue::Null => break,
+                        // Improper list
+                        _ => {
+                            write!(f, " . {}", item(&cdr))?;
+                            break;
+                        }
+                    }
+                }
+                write!(f, ")")
+            }
+        }
+    }
 }
 
 #[derive(Default)]
@@ -48,33 +390,312 @@ impl CompilispRuntime {
         args: &[CompilispValue],
     ) -> CompilispResult<CompilispValue> {
         match procedure_name {
-            "+" => compilisp_sum(args),
-            "<" => compilisp_le(args),
+            "+" => numeric(procedure_name, args, compilisp_sum),
+            "-" => numeric(procedure_name, args, compilisp_sub),
+            "*" => numeric(procedure_name, args, compilisp_product),
+            "/" => numeric(procedure_name, args, compilisp_div),
+            "=" => numeric(procedure_name, args, |args| {
+                compilisp_compare(args, Ordering::is_eq)
+            }),
+            "<" => numeric(procedure_name, args, |args| {
+                compilisp_compare(args, Ordering::is_lt)
+            }),
+            ">" => numeric(procedure_name, args, |args| {
+                compilisp_compare(args, Ordering::is_gt)
+            }),
+            "<=" => numeric(procedure_name, args, |args| {
+                compilisp_compare(args, Ordering::is_le)
+            }),
+            ">=" => numeric(procedure_name, args, |args| {
+                compilisp_compare(args, Ordering::is_ge)
+            }),
+            "quotient" => integer(procedure_name, args, |args| {
+                compilisp_integer_div("quotient", args, &QUOTIENT)
+            }),
+            "remainder" => integer(procedure_name, args, |args| {
+                compilisp_integer_div("remainder", args, &REMAINDER)
+            }),
+            "modulo" => integer(procedure_name, args, |args| {
+                compilisp_integer_div("modulo", args, &MODULO)
+            }),
+            "abs" => numeric(procedure_name, args, compilisp_abs),
+            "min" => numeric(procedure_name, args, |args| {
+                compilisp_extremum(args, Ordering::Less)
+            }),
+            "max" => numeric(procedure_name, args, |args| {
+                compilisp_extremum(args, Ordering::Greater)
+            }),
+            "zero?" => numeric(procedure_name, args, |args| {
+                compilisp_sign_test(args, Ordering::is_eq)
+            }),
+            "positive?" => numeric(procedure_name, args, |args| {
+                compilisp_sign_test(args, Ordering::is_gt)
+            }),
+            "negative?" => numeric(procedure_name, args, |args| {
+                compilisp_sign_test(args, Ordering::is_lt)
+            }),
+            "even?" => integer(procedure_name, args, compilisp_is_even),
+            "odd?" => integer(procedure_name, args, compilisp_is_odd),
+            "exact->inexact" | "inexact" => {
+                numeric(procedure_name, args, compilisp_exact_to_inexact)
+            }
+            "inexact->exact" | "exact" => numeric(procedure_name, args, compilisp_inexact_to_exact),
+            "floor" => numeric(procedure_name, args, |args| {
+                compilisp_round(args, f64::floor)
+            }),
+            "ceiling" => numeric(procedure_name, args, |args| {
+                compilisp_round(args, f64::ceil)
+            }),
+            "truncate" => numeric(procedure_name, args, |args| {
+                compilisp_round(args, f64::trunc)
+            }),
+            "round" => numeric(procedure_name, args, |args| {
+                compilisp_round(args, f64::round_ties_even)
+            }),
+            "eq?" | "eqv?" => compilisp_eq(args),
+            "cons" => compilisp_cons(args),
+            "car" => compilisp_car(args),
+            "cdr" => compilisp_cdr(args),
+            "list" => Ok(compilisp_list(args)),
+            "null?" => compilisp_is_null(args),
+            "pair?" => compilisp_is_pair(args),
+            "vector" => Ok(new_vector(args)),
+            "make-vector" => compilisp_make_vector(args),
+            "vector-ref" => compilisp_vector_ref(args),
+            "vector-set!" => compilisp_vector_set(args),
+            "vector-length" => compilisp_vector_length(args),
+            "vector->list" => compilisp_vector_to_list(args),
+            "list->vector" => compilisp_list_to_vector(args),
+            "append" => compilisp_append(args),
             "display" => {
                 for value in args {
-                    match value {
-                        CompilispValue::Number(num) => print!("{num}"),
-                        CompilispValue::Boolean(num) => print!("{num}"),
-                        CompilispValue::String(value) => print!("{value}"),
-                        CompilispValue::Symbol(_) => {
-                            panic!("Unexepected value");
-                        }
-                    }
+                    print!("{value}");
                 }
-                // TODO: void return
-                Ok(CompilispValue::Number(0))
+                Ok(CompilispValue::Unspecified)
             }
-            "begin" => args.last().cloned().ok_or(CompilispError::ArgTypeMismatch),
+            "write" => {
+                for value in args {
+                    print!("{}", Written(value));
+                }
+                Ok(CompilispValue::Unspecified)
+            }
+            "char->integer" => compilisp_char_to_integer(args),
+            "integer->char" => compilisp_integer_to_char(args),
+            "char=?" => compilisp_char_compare(args, |lhs, rhs| lhs == rhs),
+            "char<?" => compilisp_char_compare(args, |lhs, rhs| lhs < rhs),
+            "char>?" => compilisp_char_compare(args, |lhs, rhs| lhs > rhs),
+            "char<=?" => compilisp_char_compare(args, |lhs, rhs| lhs <= rhs),
+            "char>=?" => compilisp_char_compare(args, |lhs, rhs| lhs >= rhs),
+            "char-upcase" => {
+                compilisp_char_map(args, |value| single_char(*value, value.to_uppercase()))
+            }
+            "char-downcase" => {
+                compilisp_char_map(args, |value| single_char(*value, value.to_lowercase()))
+            }
+            "char-alphabetic?" => compilisp_char_test(args, char::is_alphabetic),
+            "char-numeric?" => compilisp_char_test(args, char::is_numeric),
+            "char-whitespace?" => compilisp_char_test(args, char::is_whitespace),
+            "string-length" => compilisp_string_length(args),
+            "string-ref" => compilisp_string_ref(args),
+            "substring" => compilisp_substring(args),
+            "string-append" => compilisp_string_append(args),
+            "string=?" => compilisp_string_compare(args, |lhs, rhs| lhs == rhs),
+            "string<?" => compilisp_string_compare(args, |lhs, rhs| lhs < rhs),
+            "string>?" => compilisp_string_compare(args, |lhs, rhs| lhs > rhs),
+            "string<=?" => compilisp_string_compare(args, |lhs, rhs| lhs <= rhs),
+            "string>=?" => compilisp_string_compare(args, |lhs, rhs| lhs >= rhs),
+            "string-upcase" => compilisp_string_map(args, str::to_uppercase),
+            "string-downcase" => compilisp_string_map(args, str::to_lowercase),
+            "string->number" => compilisp_string_to_number(args),
+            "number->string" => compilisp_number_to_string(args),
+            "string->symbol" => compilisp_string_to_symbol(args),
+            "symbol->string" => compilisp_symbol_to_string(args),
             _ => Err(CompilispError::UnboundVariable(procedure_name.to_string())),
         }
     }
 }
 
-fn compilisp_le(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+fn compilisp_eq(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [lhs, rhs] => {
+            let result = match (lhs, rhs) {
+                (CompilispValue::Number(lhs), CompilispValue::Number(rhs)) => lhs == rhs,
+                (CompilispValue::Boolean(lhs), CompilispValue::Boolean(rhs)) => lhs == rhs,
+                // Symbols are interned, equal names are the same symbol
+                (CompilispValue::Symbol(lhs), CompilispValue::Symbol(rhs)) => lhs == rhs,
+                (CompilispValue::Pair(lhs), CompilispValue::Pair(rhs)) => lhs == rhs,
+                (CompilispValue::Null, CompilispValue::Null) => true,
+                (CompilispValue::Vector(lhs), CompilispValue::Vector(rhs)) => lhs == rhs,
+                (CompilispValue::Char(lhs), CompilispValue::Char(rhs)) => lhs == rhs,
+                (CompilispValue::Real(lhs), CompilispValue::Real(rhs)) => lhs == rhs,
+                (CompilispValue::Bignum(lhs), CompilispValue::Bignum(rhs)) => lhs == rhs,
+                (CompilispValue::Procedure(lhs), CompilispValue::Procedure(rhs)) => lhs == rhs,
+                _ => false,
+            };
+            Ok(CompilispValue::Boolean(result))
+        }
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_cons(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [car, cdr] => Ok(new_pair(car, cdr)),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_car(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Pair(pair)] => Ok(pair_values(*pair).0),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_cdr(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Pair(pair)] => Ok(pair_values(*pair).1),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_list(args: &[CompilispValue]) -> CompilispValue {
+    args.iter()
+        .rev()
+        .fold(CompilispValue::Null, |list, value| new_pair(value, &list))
+}
+
+fn compilisp_is_null(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [value] => Ok(CompilispValue::Boolean(matches!(
+            value,
+            CompilispValue::Null
+        ))),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_is_pair(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [value] => Ok(CompilispValue::Boolean(matches!(
+            value,
+            CompilispValue::Pair(_)
+        ))),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_make_vector(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    let (size, fill) = match args {
+        [CompilispValue::Number(size)] => (*size, CompilispValue::Number(0)),
+        [CompilispValue::Number(size), fill] => (*size, fill.clone()),
+        _ => return Err(CompilispError::ArgTypeMismatch),
+    };
+    let size = usize::try_from(size).map_err(|_| CompilispError::IndexOutOfRange(size))?;
+    Ok(new_vector(&vec![fill; size]))
+}
+
+fn compilisp_vector_ref(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Vector(vector), CompilispValue::Number(index)] => {
+            let slot = vector_slot(*vector, *index)?;
+            CompilispValue::try_from(&*slot).map_err(|_| CompilispError::ArgTypeMismatch)
+        }
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_vector_set(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Vector(vector), CompilispValue::Number(index), value] => {
+            *vector_slot(*vector, *index)? = CompilispObject::from(value);
+            Ok(CompilispValue::Unspecified)
+        }
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_vector_length(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Vector(vector)] => {
+            let len = unsafe { (**vector).len() };
+            Ok(CompilispValue::Number(len as i32))
+        }
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_vector_to_list(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Vector(vector)] => Ok(compilisp_list(&vector_values(*vector))),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_list_to_vector(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [list] => match list_values(list) {
+            Some(values) => Ok(new_vector(&values)),
+            None => Err(CompilispError::WrongType(
+                Written(list).to_string(),
+                0,
+                "list->vector".to_owned(),
+            )),
+        },
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+/// Copies every list but the last argument, which is shared and can be any object
+fn compilisp_append(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    let Some((last, lists)) = args.split_last() else {
+        return Ok(CompilispValue::Null);
+    };
+    let mut values = Vec::new();
+    for (index, list) in lists.iter().enumerate() {
+        match list_values(list) {
+            Some(items) => values.extend(items),
+            None => {
+                return Err(CompilispError::WrongType(
+                    Written(list).to_string(),
+                    index,
+                    "append".to_owned(),
+                ))
+            }
+        }
+    }
+    Ok(values
+        .iter()
+        .rev()
+        .fold(last.clone(), |list, value| new_pair(value, &list)))
+}
+
+fn compilisp_char_to_integer(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Char(value)] => Ok(CompilispValue::Number(*value as i32)),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_integer_to_char(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Number(value)] => u32::try_from(*value)
+            .ok()
+            .and_then(char::from_u32)
+            .map(CompilispValue::Char)
+            .ok_or(CompilispError::ArgTypeMismatch),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_char_compare(
+    args: &[CompilispValue],
+    compare: fn(char, char) -> bool,
+) -> CompilispResult<CompilispValue> {
     for slice in args.windows(2) {
         match (&slice[0], &slice[1]) {
-            (CompilispValue::Number(lhs), CompilispValue::Number(rhs)) => {
-                if lhs >= rhs {
+            (CompilispValue::Char(lhs), CompilispValue::Char(rhs)) => {
+                if !compare(*lhs, *rhs) {
                     return Ok(CompilispValue::Boolean(false));
                 }
             }
@@ -84,19 +705,496 @@ fn compilisp_le(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
     Ok(CompilispValue::Boolean(true))
 }
 
-fn compilisp_sum(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
-    let mut result = 0;
+fn compilisp_char_map(
+    args: &[CompilispValue],
+    map: fn(&char) -> char,
+) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Char(value)] => Ok(CompilispValue::Char(map(value))),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+/// Case mapping of a character, characters mapped to several ones are kept as they are
+fn single_char(value: char, mut mapped: impl Iterator<Item = char>) -> char {
+    match (mapped.next(), mapped.next()) {
+        (Some(mapped), None) => mapped,
+        _ => value,
+    }
+}
+
+fn compilisp_char_test(
+    args: &[CompilispValue],
+    test: fn(char) -> bool,
+) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Char(value)] => Ok(CompilispValue::Boolean(test(*value))),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_string_length(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::String(value)] => Ok(CompilispValue::Number(value.chars().count() as i32)),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_string_ref(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::String(value), CompilispValue::Number(index)] => usize::try_from(*index)
+            .ok()
+            .and_then(|i| value.chars().nth(i))
+            .map(CompilispValue::Char)
+            .ok_or(CompilispError::IndexOutOfRange(*index)),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+/// Characters from `start` up to `end`, or up to the end of the string
+fn compilisp_substring(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    let (value, start, end) = match args {
+        [CompilispValue::String(value), CompilispValue::Number(start)] => {
+            (value, *start, value.chars().count() as i32)
+        }
+        [CompilispValue::String(value), CompilispValue::Number(start), CompilispValue::Number(end)] => {
+            (value, *start, *end)
+        }
+        _ => return Err(CompilispError::ArgTypeMismatch),
+    };
+    let len = value.chars().count() as i32;
+    if !(0..=len).contains(&end) {
+        return Err(CompilispError::IndexOutOfRange(end));
+    }
+    if !(0..=end).contains(&start) {
+        return Err(CompilispError::IndexOutOfRange(start));
+    }
+    let substring = value
+        .chars()
+        .skip(start as usize)
+        .take((end - start) as usize)
+        .collect();
+    Ok(CompilispValue::String(substring))
+}
+
+fn compilisp_string_append(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    let mut result = String::new();
     for arg in args {
         match arg {
-            CompilispValue::Number(value) => {
-                result += value;
+            CompilispValue::String(value) => result.push_str(value),
+            _ => return Err(CompilispError::ArgTypeMismatch),
+        }
+    }
+    Ok(CompilispValue::String(result))
+}
+
+fn compilisp_string_compare(
+    args: &[CompilispValue],
+    compare: fn(&str, &str) -> bool,
+) -> CompilispResult<CompilispValue> {
+    for slice in args.windows(2) {
+        match (&slice[0], &slice[1]) {
+            (CompilispValue::String(lhs), CompilispValue::String(rhs)) => {
+                if !compare(lhs, rhs) {
+                    return Ok(CompilispValue::Boolean(false));
+                }
             }
-            _ => {
-                return Err(CompilispError::ArgTypeMismatch);
+            _ => return Err(CompilispError::ArgTypeMismatch),
+        }
+    }
+    Ok(CompilispValue::Boolean(true))
+}
+
+fn compilisp_string_map(
+    args: &[CompilispValue],
+    map: fn(&str) -> String,
+) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::String(value)] => Ok(CompilispValue::String(map(value))),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn radix(args: &[CompilispValue]) -> CompilispResult<u32> {
+    match args {
+        [] => Ok(10),
+        [CompilispValue::Number(radix @ (2 | 8 | 10 | 16))] => Ok(*radix as u32),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+/// Parses the number written in the string, `#f` if it isn't a number.
+/// Only decimal numbers can be inexact
+fn compilisp_string_to_number(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    let (value, radix) = match args {
+        [CompilispValue::String(value), radix_args @ ..] => (value, radix(radix_args)?),
+        _ => return Err(CompilispError::ArgTypeMismatch),
+    };
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+    if !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix)) {
+        let value = BigInt::parse_bytes(value.as_bytes(), radix).expect("Digits of the radix");
+        return Ok(exact_integer(value));
+    }
+    // Rust also parses names like `inf`, which aren't scheme numbers
+    let is_decimal = value.chars().any(|c| c.is_ascii_digit())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+    match value.parse::<f64>() {
+        Ok(value) if radix == 10 && is_decimal => Ok(CompilispValue::Real(value)),
+        _ => Ok(CompilispValue::Boolean(false)),
+    }
+}
+
+fn compilisp_number_to_string(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    let (value, radix) = match args {
+        [value, radix_args @ ..] => (value, radix(radix_args)?),
+        _ => return Err(CompilispError::ArgTypeMismatch),
+    };
+    match (to_bignum(value), value) {
+        (Some(value), _) => Ok(CompilispValue::String(value.to_str_radix(radix))),
+        (None, CompilispValue::Real(_)) if radix == 10 => {
+            Ok(CompilispValue::String(value.to_string()))
+        }
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_string_to_symbol(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::String(value)] => Ok(CompilispValue::Symbol(value.clone())),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn compilisp_symbol_to_string(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [CompilispValue::Symbol(name)] => Ok(CompilispValue::String(name.clone())),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn to_real(value: &CompilispValue) -> CompilispResult<f64> {
+    match value {
+        CompilispValue::Number(value) => Ok(*value as f64),
+        CompilispValue::Real(value) => Ok(*value),
+        CompilispValue::Bignum(value) => Ok(value.to_f64().unwrap_or(f64::NAN)),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+fn to_bignum(value: &CompilispValue) -> Option<BigInt> {
+    match value {
+        CompilispValue::Number(value) => Some(BigInt::from(*value)),
+        CompilispValue::Bignum(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Exact integers are kept as fixnums while they fit
+fn exact_integer(value: BigInt) -> CompilispValue {
+    match i32::try_from(&value) {
+        Ok(value) => CompilispValue::Number(value),
+        Err(_) => CompilispValue::Bignum(value),
+    }
+}
+
+/// Reports the first argument the procedure doesn't accept
+fn check_args(
+    name: &str,
+    args: &[CompilispValue],
+    accepts: fn(&CompilispValue) -> bool,
+) -> CompilispResult<()> {
+    match args.iter().position(|arg| !accepts(arg)) {
+        Some(index) => Err(CompilispError::WrongType(
+            Written(&args[index]).to_string(),
+            index,
+            name.to_owned(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Applies a procedure on numbers, once every argument is checked to be a number
+fn numeric(
+    name: &str,
+    args: &[CompilispValue],
+    procedure: fn(&[CompilispValue]) -> CompilispResult<CompilispValue>,
+) -> CompilispResult<CompilispValue> {
+    check_args(name, args, CompilispValue::is_number)?;
+    procedure(args)
+}
+
+/// Applies a procedure on integers, once every argument is checked to be an integer
+fn integer(
+    name: &str,
+    args: &[CompilispValue],
+    procedure: fn(&[CompilispValue]) -> CompilispResult<CompilispValue>,
+) -> CompilispResult<CompilispValue> {
+    check_args(name, args, CompilispValue::is_integer)?;
+    procedure(args)
+}
+
+/// Arithmetic operation on fixnums, bignums and reals
+struct NumericOp {
+    /// Fails when the result overflows, it's then computed with bignums
+    fixnum: fn(i32, i32) -> Option<i32>,
+    bignum: fn(BigInt, BigInt) -> BigInt,
+    real: fn(f64, f64) -> f64,
+}
+
+const ADD: NumericOp = NumericOp {
+    fixnum: i32::checked_add,
+    bignum: |lhs, rhs| lhs + rhs,
+    real: |lhs, rhs| lhs + rhs,
+};
+
+const SUB: NumericOp = NumericOp {
+    fixnum: i32::checked_sub,
+    bignum: |lhs, rhs| lhs - rhs,
+    real: |lhs, rhs| lhs - rhs,
+};
+
+const MUL: NumericOp = NumericOp {
+    fixnum: i32::checked_mul,
+    bignum: |lhs, rhs| lhs * rhs,
+    real: |lhs, rhs| lhs * rhs,
+};
+
+/// Truncates the quotient towards zero
+const QUOTIENT: NumericOp = NumericOp {
+    fixnum: i32::checked_div,
+    bignum: |lhs, rhs| lhs / rhs,
+    real: |lhs, rhs| (lhs / rhs).trunc(),
+};
+
+/// Has the sign of the dividend
+const REMAINDER: NumericOp = NumericOp {
+    fixnum: i32::checked_rem,
+    bignum: |lhs, rhs| lhs % rhs,
+    real: |lhs, rhs| lhs % rhs,
+};
+
+/// Has the sign of the divisor
+const MODULO: NumericOp = NumericOp {
+    fixnum: |lhs, rhs| lhs.checked_rem(rhs).map(|rem| rem.mod_floor(&rhs)),
+    bignum: |lhs, rhs| lhs.mod_floor(&rhs),
+    real: |lhs, rhs| {
+        let rem = lhs % rhs;
+        if rem != 0.0 && (rem < 0.0) != (rhs < 0.0) {
+            rem + rhs
+        } else {
+            rem
+        }
+    },
+};
+
+/// Applies the operation to two numbers, the result is inexact if any of them is inexact
+fn combine(
+    lhs: &CompilispValue,
+    rhs: &CompilispValue,
+    op: &NumericOp,
+) -> CompilispResult<CompilispValue> {
+    match (lhs, rhs) {
+        (CompilispValue::Number(lhs), CompilispValue::Number(rhs)) => {
+            if let Some(result) = (op.fixnum)(*lhs, *rhs) {
+                return Ok(CompilispValue::Number(result));
             }
         }
+        (CompilispValue::Real(_), _) | (_, CompilispValue::Real(_)) => {
+            return Ok(CompilispValue::Real((op.real)(
+                to_real(lhs)?,
+                to_real(rhs)?,
+            )));
+        }
+        _ => {}
+    }
+    match (to_bignum(lhs), to_bignum(rhs)) {
+        (Some(lhs), Some(rhs)) => Ok(exact_integer((op.bignum)(lhs, rhs))),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+/// Exact integers are compared exactly, NaN isn't ordered with any number
+fn compare(lhs: &CompilispValue, rhs: &CompilispValue) -> CompilispResult<Option<Ordering>> {
+    match (lhs, rhs) {
+        (CompilispValue::Number(lhs), CompilispValue::Number(rhs)) => Ok(Some(lhs.cmp(rhs))),
+        (lhs, rhs) => match (to_bignum(lhs), to_bignum(rhs)) {
+            (Some(lhs), Some(rhs)) => Ok(Some(lhs.cmp(&rhs))),
+            _ => Ok(to_real(lhs)?.partial_cmp(&to_real(rhs)?)),
+        },
+    }
+}
+
+/// Compares every pair of consecutive numbers
+fn compilisp_compare(
+    args: &[CompilispValue],
+    test: fn(Ordering) -> bool,
+) -> CompilispResult<CompilispValue> {
+    for slice in args.windows(2) {
+        if !compare(&slice[0], &slice[1])?.is_some_and(test) {
+            return Ok(CompilispValue::Boolean(false));
+        }
+    }
+    Ok(CompilispValue::Boolean(true))
+}
+
+fn compilisp_sum(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    args.iter()
+        .try_fold(CompilispValue::Number(0), |result, value| {
+            combine(&result, value, &ADD)
+        })
+}
+
+fn compilisp_sub(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [] => Err(CompilispError::WrongArity(0, 1, true)),
+        [value] => combine(&CompilispValue::Number(0), value, &SUB),
+        [first, rest @ ..] => rest
+            .iter()
+            .try_fold(first.clone(), |result, value| combine(&result, value, &SUB)),
+    }
+}
+
+fn compilisp_product(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    args.iter()
+        .try_fold(CompilispValue::Number(1), |result, value| {
+            combine(&result, value, &MUL)
+        })
+}
+
+fn compilisp_div(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [] => Err(CompilispError::WrongArity(0, 1, true)),
+        [value] => divide(&CompilispValue::Number(1), value),
+        [first, rest @ ..] => rest
+            .iter()
+            .try_fold(first.clone(), |result, value| divide(&result, value)),
+    }
+}
+
+/// There are no exact rationals, exact division that leaves a remainder is inexact
+fn divide(lhs: &CompilispValue, rhs: &CompilispValue) -> CompilispResult<CompilispValue> {
+    if let CompilispValue::Number(0) = rhs {
+        return Err(CompilispError::DivisionByZero("/".to_owned()));
+    }
+    match (to_bignum(lhs), to_bignum(rhs)) {
+        (Some(lhs), Some(rhs)) if (&lhs % &rhs).is_zero() => Ok(exact_integer(lhs / rhs)),
+        _ => Ok(CompilispValue::Real(to_real(lhs)? / to_real(rhs)?)),
+    }
+}
+
+fn compilisp_integer_div(
+    name: &str,
+    args: &[CompilispValue],
+    op: &NumericOp,
+) -> CompilispResult<CompilispValue> {
+    match args {
+        [lhs, rhs] => {
+            if compare(rhs, &CompilispValue::Number(0))? == Some(Ordering::Equal) {
+                return Err(CompilispError::DivisionByZero(name.to_owned()));
+            }
+            combine(lhs, rhs, op)
+        }
+        _ => Err(CompilispError::WrongArity(args.len() as u32, 2, false)),
+    }
+}
+
+fn compilisp_abs(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [value] => match compare(value, &CompilispValue::Number(0))? {
+            Some(Ordering::Less) => combine(&CompilispValue::Number(0), value, &SUB),
+            _ => Ok(value.clone()),
+        },
+        _ => Err(CompilispError::WrongArity(args.len() as u32, 1, false)),
+    }
+}
+
+/// Picks the number with the given ordering to every other, inexact if any number is inexact
+fn compilisp_extremum(args: &[CompilispValue], pick: Ordering) -> CompilispResult<CompilispValue> {
+    let (first, rest) = args
+        .split_first()
+        .ok_or(CompilispError::WrongArity(0, 1, true))?;
+    let mut result = first.clone();
+    for value in rest {
+        if compare(value, &result)? == Some(pick) {
+            result = value.clone();
+        }
+    }
+    if args
+        .iter()
+        .any(|value| matches!(value, CompilispValue::Real(_)))
+    {
+        Ok(CompilispValue::Real(to_real(&result)?))
+    } else {
+        Ok(result)
+    }
+}
+
+/// Tests the ordering of the number with zero
+fn compilisp_sign_test(
+    args: &[CompilispValue],
+    test: fn(Ordering) -> bool,
+) -> CompilispResult<CompilispValue> {
+    match args {
+        [value] => {
+            let ordering = compare(value, &CompilispValue::Number(0))?;
+            Ok(CompilispValue::Boolean(ordering.is_some_and(test)))
+        }
+        _ => Err(CompilispError::WrongArity(args.len() as u32, 1, false)),
+    }
+}
+
+fn compilisp_is_even(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [value] => {
+            let even = match value {
+                CompilispValue::Number(value) => value.is_even(),
+                CompilispValue::Bignum(value) => value.is_even(),
+                CompilispValue::Real(value) => value % 2.0 == 0.0,
+                _ => return Err(CompilispError::ArgTypeMismatch),
+            };
+            Ok(CompilispValue::Boolean(even))
+        }
+        _ => Err(CompilispError::WrongArity(args.len() as u32, 1, false)),
+    }
+}
+
+fn compilisp_is_odd(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match compilisp_is_even(args)? {
+        CompilispValue::Boolean(even) => Ok(CompilispValue::Boolean(!even)),
+        _ => unreachable!("even? returns a boolean"),
+    }
+}
+
+fn compilisp_exact_to_inexact(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [value] => Ok(CompilispValue::Real(to_real(value)?)),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+/// Only integral reals have an exact representation
+fn compilisp_inexact_to_exact(args: &[CompilispValue]) -> CompilispResult<CompilispValue> {
+    match args {
+        [value @ (CompilispValue::Number(_) | CompilispValue::Bignum(_))] => Ok(value.clone()),
+        [CompilispValue::Real(value)] if value.fract() == 0.0 => BigInt::from_f64(*value)
+            .map(exact_integer)
+            .ok_or(CompilispError::ArgTypeMismatch),
+        _ => Err(CompilispError::ArgTypeMismatch),
+    }
+}
+
+/// Rounds inexact numbers to an integral inexact number, exact integers are already integral
+fn compilisp_round(
+    args: &[CompilispValue],
+    round: fn(f64) -> f64,
+) -> CompilispResult<CompilispValue> {
+    match args {
+        [value @ (CompilispValue::Number(_) | CompilispValue::Bignum(_))] => Ok(value.clone()),
+        [CompilispValue::Real(value)] => Ok(CompilispValue::Real(round(*value))),
+        _ => Err(CompilispError::ArgTypeMismatch),
     }
-    Ok(CompilispValue::Number(result))
 }
 
 impl TryFrom<&CompilispObject> for CompilispValue {
@@ -110,6 +1208,24 @@ impl TryFrom<&CompilispObject> for CompilispValue {
                 let s = CStr::from_ptr(obj.value.str_value);
                 Ok(CompilispValue::String(s.to_str().unwrap().to_string()))
             },
+            CompilispType::Symbol => unsafe {
+                let s = CStr::from_ptr(obj.value.str_value);
+                Ok(CompilispValue::Symbol(s.to_str().unwrap().to_string()))
+            },
+            CompilispType::Procedure => Ok(CompilispValue::Procedure(unsafe {
+                obj.value.closure_value
+            })),
+            CompilispType::Pair => Ok(CompilispValue::Pair(unsafe { obj.value.pair_value })),
+            CompilispType::Null => Ok(CompilispValue::Null),
+            CompilispType::Vector => Ok(CompilispValue::Vector(unsafe { obj.value.vector_value })),
+            CompilispType::Unspecified => Ok(CompilispValue::Unspecified),
+            CompilispType::Char => char::from_u32(unsafe { obj.value.char_value })
+                .map(CompilispValue::Char)
+                .ok_or(()),
+            CompilispType::Real => Ok(CompilispValue::Real(unsafe { obj.value.real_value })),
+            CompilispType::Bignum => Ok(CompilispValue::Bignum(unsafe {
+                (*obj.value.bignum_value).clone()
+            })),
             _ => Err(()),
         }
     }
@@ -132,7 +1248,88 @@ impl From<&CompilispValue> for CompilispObject {
                     value,
                 }
             }
-            _ => todo!("Unsupported return value"),
+            CompilispValue::String(value) => {
+                let value = CompilispObjectValue {
+                    str_value: CString::new(value.as_str()).unwrap().into_raw(),
+                };
+                Self {
+                    type_: CompilispType::String,
+                    value,
+                }
+            }
+            CompilispValue::Symbol(name) => {
+                let value = CompilispObjectValue {
+                    str_value: intern_symbol(name),
+                };
+                Self {
+                    type_: CompilispType::Symbol,
+                    value,
+                }
+            }
+            CompilispValue::Procedure(closure) => {
+                let value = CompilispObjectValue {
+                    closure_value: *closure,
+                };
+                Self {
+                    type_: CompilispType::Procedure,
+                    value,
+                }
+            }
+            CompilispValue::Pair(pair) => {
+                let value = CompilispObjectValue { pair_value: *pair };
+                Self {
+                    type_: CompilispType::Pair,
+                    value,
+                }
+            }
+            CompilispValue::Vector(vector) => {
+                let value = CompilispObjectValue {
+                    vector_value: *vector,
+                };
+                Self {
+                    type_: CompilispType::Vector,
+                    value,
+                }
+            }
+            CompilispValue::Null => {
+                let value = CompilispObjectValue { int_value: 0 };
+                Self {
+                    type_: CompilispType::Null,
+                    value,
+                }
+            }
+            CompilispValue::Char(value) => {
+                let value = CompilispObjectValue {
+                    char_value: *value as u32,
+                };
+                Self {
+                    type_: CompilispType::Char,
+                    value,
+                }
+            }
+            CompilispValue::Real(value) => {
+                let value = CompilispObjectValue { real_value: *value };
+                Self {
+                    type_: CompilispType::Real,
+                    value,
+                }
+            }
+            CompilispValue::Bignum(value) => {
+                let value = CompilispObjectValue {
+                    bignum_value: Box::into_raw(Box::new(value.clone())),
+                };
+                Self {
+                    type_: CompilispType::Bignum,
+                    value,
+                }
+            }
+            CompilispValue::Unspecified => {
+                let value = CompilispObjectValue { int_value: 0 };
+                Self {
+                    type_: CompilispType::Unspecified,
+                    value,
+                }
+            }
         }
     }
 }
@@ -25,7 +25,35 @@ def build_compiler():
         "conditional_display",
         "define_expr_01",
         "define_procedure_01",
-        "define_procedure_02"
+        "define_procedure_02",
+        "named_let",
+        "named_let_recursive",
+        "do_loop",
+        "let_scoping",
+        "letrec_even_odd",
+        "lambda_closure",
+        "tail_recursion",
+        "derived_forms",
+        "set_assignment",
+        "quote_symbols",
+        "pairs_lists",
+        "vectors",
+        "variadic",
+        "booleans",
+        "truthiness",
+        "if_results",
+        "chars",
+        "flonums",
+        "bignums",
+        "arithmetic",
+        "strings",
+        "comments",
+        "macros",
+        "quasiquote",
+        "bodies",
+        "begin",
+        "globals",
+        "user_primitives"
     ]
 )
 def test_compile_and_run(testcase):